@@ -2,9 +2,18 @@ use crate::EntryType::*;
 use clap::{Parser, ValueEnum};
 use regex::Regex;
 use std::{
+    cmp::Ordering,
+    env,
     error::Error,
-    fs::File,
+    ffi::{OsStr, OsString},
+    fs::{self, File, Metadata},
     io::{self, BufRead, BufReader},
+    iter::Peekable,
+    mem,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    slice,
+    time::{SystemTime, UNIX_EPOCH},
 };
 use walkdir::{DirEntry, WalkDir};
 
@@ -25,42 +34,355 @@ enum EntryType {
 
 // impl
 
+#[derive(Debug, Clone, Copy)]
+enum Timestamp {
+    Accessed,
+    Changed,
+    Modified,
+}
+
+impl Timestamp {
+    fn seconds(self, metadata: &Metadata) -> io::Result<f64> {
+        match self {
+            Timestamp::Accessed => metadata.accessed().map(epoch_seconds),
+            Timestamp::Changed => Ok(changed_seconds(metadata)),
+            Timestamp::Modified => metadata.modified().map(epoch_seconds),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn changed_seconds(metadata: &Metadata) -> f64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ctime() as f64 + metadata.ctime_nsec() as f64 / 1e9
+}
+
+// There is no status change time outside Unix, so use the closest thing
+#[cfg(not(unix))]
+fn changed_seconds(metadata: &Metadata) -> f64 {
+    metadata.modified().map(epoch_seconds).unwrap_or(0.0)
+}
+
+fn epoch_seconds(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs_f64(),
+        Err(err) => -err.duration().as_secs_f64(),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AgeUnit {
+    Days,
+    Minutes,
+}
+
+impl AgeUnit {
+    /// Compare an age in seconds against N units the way GNU find does:
+    /// whole days are counted by truncating, while minutes are only rounded
+    /// (up) when asking for exactly N.
+    fn matches(self, age: f64, ordering: Ordering, n: u64) -> bool {
+        let n = n as f64;
+        let age = match (self, ordering) {
+            (AgeUnit::Days, _) => (age / 86400.0).floor(),
+            (AgeUnit::Minutes, Ordering::Equal) => (age / 60.0).ceil(),
+            (AgeUnit::Minutes, _) => age / 60.0,
+        };
+        age.partial_cmp(&n) == Some(ordering)
+    }
+}
+
+const EXPRESSION_HELP: &str = "\
+Expression:
+  -n, -name PATTERN     Entry name matches the regex PATTERN
+  -path PATTERN         Entry path matches the regex PATTERN
+  -t, -type TYPE        Entry type is one of TYPE: d, f or l, comma-separated
+  -size [+-]N[cwbkMG]   Size is more than (+N), less than (-N) or exactly N units
+                        of c bytes, w 2-byte words, b 512-byte blocks (default),
+                        k KiB, M MiB or G GiB, rounding the size up to units
+  -mtime [+-]N          Modified more (+N), less (-N) or exactly N days ago,
+                        ignoring any fraction of a day
+  -atime, -ctime [+-]N  Likewise for the access and status change times
+  -mmin, -amin, -cmin [+-]N
+                        As above in minutes, where N means N-1 < age <= N
+  -newer FILE           Modified more recently than FILE
+  -print                Print the path; implied when there is no other action
+  -exec CMD ;           Run CMD with every {} in it replaced by the path,
+                        true when CMD succeeds
+  -exec CMD {} +        Run CMD with as many paths appended as fit on a command
+                        line; always true, but findr fails if CMD does
+  -ok CMD ;             As -exec CMD ; after confirming on standard error
+  ! EXPR, -not EXPR     EXPR is false
+  EXPR [-a|-and] EXPR   Both are true
+  EXPR -o|-or EXPR      Either is true
+  ( EXPR )              Grouping
+  -maxdepth N           Descend at most N levels below the search paths
+  -mindepth N           Skip entries less than N levels below the search paths
+
+`!` binds tighter than -a, which binds tighter than -o, as in GNU find.
+-maxdepth and -mindepth apply to the whole search wherever they appear.
+Long forms such as --name and --type are accepted too.
+Remember to quote ; and ( ) from the shell.
+Set FINDR_NOW to seconds since the Unix epoch to fix the time that ages
+are measured from.";
+
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = EXPRESSION_HELP)]
 pub struct Args {
-    #[arg(help = "Search paths", id = "PATH", default_value = ".")]
+    #[arg(
+        help = "Search paths, followed by an expression",
+        id = "PATH",
+        allow_hyphen_values = true,
+        trailing_var_arg = true
+    )]
+    args: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Config {
     paths: Vec<String>,
+    expr: Expr,
+    min_depth: usize,
+    max_depth: Option<usize>,
+}
+
+// Assumed where the system cannot report its ARG_MAX: the limit Linux had
+// before 2.6.23, and well above the POSIX minimum
+const FALLBACK_ARG_MAX: usize = 128 * 1024;
+
+/// The most bytes of arguments and environment that a new process can be
+/// given, which batches must leave room in for the command itself and the
+/// environment.
+#[cfg(unix)]
+fn arg_max() -> usize {
+    // SAFETY: sysconf only reads a limit and takes no pointers
+    match unsafe { libc::sysconf(libc::_SC_ARG_MAX) } {
+        max if max > 0 => max as usize,
+        _ => FALLBACK_ARG_MAX,
+    }
+}
+
+#[cfg(not(unix))]
+fn arg_max() -> usize {
+    FALLBACK_ARG_MAX
+}
 
-    #[arg(short = 'n', long = "name", help = "Name", id = "NAME", num_args=0..)]
-    names: Vec<Regex>,
+/// How `-exec` and `-ok` run their command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExecMode {
+    Each,
+    Batch,
+    Ask,
+}
 
-    #[arg(short = 't', long = "type", help = "Entry type", id = "TYPE", num_args=0..)]
-    types: Vec<EntryType>,
+#[derive(Debug)]
+struct Exec {
+    mode: ExecMode,
+    command: Vec<String>,
+    batch: Vec<PathBuf>,
+    batch_size: usize,
+    batch_limit: usize,
+    failed: bool,
 }
 
-pub fn run(args: Args) -> MyResult<()> {
-    // dbg!(args);
-    let is_valid_type = |entry: &DirEntry| -> bool {
-        args.types.is_empty()
-            || args.types.iter().any(|etype| match etype {
+impl Exec {
+    fn new(mode: ExecMode, command: Vec<String>) -> Self {
+        let env_size: usize = env::vars_os()
+            .map(|(key, value)| arg_size(&key) + arg_size(&value))
+            .sum();
+        let command_size: usize = command.iter().map(|arg| arg_size(arg.as_ref())).sum();
+
+        Exec {
+            mode,
+            command,
+            batch: vec![],
+            batch_size: 0,
+            batch_limit: arg_max().saturating_sub(env_size + command_size + 2048),
+            failed: false,
+        }
+    }
+
+    fn run(&mut self, path: &Path) -> bool {
+        if self.mode == ExecMode::Batch {
+            let size = arg_size(path.as_os_str());
+            if !self.batch.is_empty() && self.batch_size + size > self.batch_limit {
+                self.flush();
+            }
+            self.batch_size += size;
+            self.batch.push(path.to_path_buf());
+            return true;
+        }
+
+        let args: Vec<_> = self
+            .command
+            .iter()
+            .map(|arg| substitute(arg, path.as_os_str()))
+            .collect();
+        let mut command = Command::new(&args[0]);
+        command.args(&args[1..]);
+
+        if self.mode == ExecMode::Ask {
+            let prompt: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+            eprint!("< {} > ? ", prompt.join(" "));
+            let mut reply = String::new();
+            if io::stdin().read_line(&mut reply).is_err()
+                || !reply.trim_start().starts_with(['y', 'Y'])
+            {
+                return false;
+            }
+            command.stdin(Stdio::null());
+        }
+
+        self.status(command)
+    }
+
+    fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+
+        let mut command = Command::new(&self.command[0]);
+        command
+            .args(&self.command[1..])
+            .args(mem::take(&mut self.batch));
+        self.batch_size = 0;
+        if !self.status(command) {
+            self.failed = true;
+        }
+    }
+
+    fn status(&mut self, mut command: Command) -> bool {
+        match command.status() {
+            Ok(status) => status.success(),
+            Err(err) => {
+                eprintln!("{}: {}", self.command[0], err);
+                self.failed = true;
+                false
+            }
+        }
+    }
+}
+
+/// The bytes an argument takes up on a command line, counting its
+/// terminating NUL and pointer as the kernel does.
+fn arg_size(arg: &OsStr) -> usize {
+    arg.len() + 1 + mem::size_of::<usize>()
+}
+
+fn substitute(arg: &str, path: &OsStr) -> OsString {
+    let mut parts = arg.split("{}");
+    let mut result = OsString::from(parts.next().unwrap_or_default());
+    for part in parts {
+        result.push(path);
+        result.push(part);
+    }
+    result
+}
+
+/// A parsed expression, evaluated against each entry found.
+#[derive(Debug)]
+enum Expr {
+    True,
+    Name(Regex),
+    Path(Regex),
+    Type(Vec<EntryType>),
+    Size(Ordering, u64, u64),
+    Age {
+        timestamp: Timestamp,
+        ordering: Ordering,
+        n: u64,
+        unit: AgeUnit,
+        now: f64,
+    },
+    Newer(f64),
+    Print,
+    Exec(Exec),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&mut self, entry: &DirEntry) -> bool {
+        match self {
+            Expr::True => true,
+            Expr::Name(re) => re.is_match(&entry.file_name().to_string_lossy()),
+            Expr::Path(re) => re.is_match(&entry.path().to_string_lossy()),
+            Expr::Type(types) => types.iter().any(|etype| match etype {
                 Dir => entry.file_type().is_dir(),
                 File => entry.file_type().is_file(),
                 Link => entry.file_type().is_symlink(),
-            })
-    };
+            }),
+            Expr::Size(ordering, size, unit) => entry
+                .metadata()
+                .map(|metadata| metadata.len().div_ceil(*unit).cmp(size) == *ordering)
+                .unwrap_or(false),
+            Expr::Age {
+                timestamp,
+                ordering,
+                n,
+                unit,
+                now,
+            } => entry
+                .metadata()
+                .map_err(io::Error::from)
+                .and_then(|metadata| timestamp.seconds(&metadata))
+                .map(|seconds| unit.matches(*now - seconds, *ordering, *n))
+                .unwrap_or(false),
+            Expr::Newer(than) => entry
+                .metadata()
+                .map_err(io::Error::from)
+                .and_then(|metadata| Timestamp::Modified.seconds(&metadata))
+                .map(|seconds| seconds > *than)
+                .unwrap_or(false),
+            Expr::Print => {
+                println!("{}", entry.path().display());
+                true
+            }
+            Expr::Exec(exec) => exec.run(entry.path()),
+            Expr::Not(expr) => !expr.eval(entry),
+            Expr::And(left, right) => left.eval(entry) && right.eval(entry),
+            Expr::Or(left, right) => left.eval(entry) || right.eval(entry),
+        }
+    }
 
-    let is_valid_name = |entry: &DirEntry| -> bool {
-        args.names.is_empty()
-            || args
-                .names
-                .iter()
-                .any(|re| re.is_match(&entry.file_name().to_string_lossy()))
-    };
+    fn has_action(&self) -> bool {
+        match self {
+            Expr::Print | Expr::Exec(_) => true,
+            Expr::Not(expr) => expr.has_action(),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.has_action() || right.has_action()
+            }
+            _ => false,
+        }
+    }
+
+    /// Run any pending batches, returning whether every command could run
+    /// and every batch succeeded.
+    fn finish(&mut self) -> bool {
+        match self {
+            Expr::Exec(exec) => {
+                exec.flush();
+                !exec.failed
+            }
+            Expr::Not(expr) => expr.finish(),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                let left = left.finish();
+                right.finish() && left
+            }
+            _ => true,
+        }
+    }
+}
 
-    for path in args.paths {
+pub fn run(mut cfg: Config) -> MyResult<()> {
+    // dbg!(cfg);
+    for path in &cfg.paths {
         // let entries = WalkDir::new(path)
-        WalkDir::new(path)
-            .into_iter()
+        let mut walk = WalkDir::new(path).min_depth(cfg.min_depth);
+        if let Some(depth) = cfg.max_depth {
+            walk = walk.max_depth(depth);
+        }
+        walk.into_iter()
             .filter_map(|e| match e {
                 Err(e) => {
                     eprintln!("{}", e);
@@ -68,22 +390,45 @@ pub fn run(args: Args) -> MyResult<()> {
                 }
                 Ok(entry) => Some(entry),
             })
-            .filter(is_valid_type)
-            .filter(is_valid_name)
-            .for_each(|entry| println!("{}", entry.path().display()));
+            .for_each(|entry| {
+                cfg.expr.eval(&entry);
+            });
         // .map(|entry| entry.path().display().to_string())
         // .collect::<Vec<_>>();
 
         // println!("{}", entries.join("\n"));
     }
 
+    if !cfg.expr.finish() {
+        return Err(From::from("one or more commands failed"));
+    }
     Ok(())
 }
 
-pub fn get_args() -> MyResult<Args> {
+pub fn get_args() -> MyResult<Config> {
     let args = Args::parse();
 
-    Ok(args)
+    // As in GNU find, the expression starts at the first argument that
+    // looks like part of one
+    let split = args
+        .args
+        .iter()
+        .position(|arg| arg.len() > 1 && arg.starts_with('-') || ["(", ")", "!"].contains(&&**arg))
+        .unwrap_or(args.args.len());
+    let (paths, expr) = args.args.split_at(split);
+
+    let paths = match paths {
+        [] => vec![".".to_string()],
+        _ => paths.to_vec(),
+    };
+    let now = match env::var("FINDR_NOW") {
+        Ok(now) => now
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid FINDR_NOW \"{}\"", now))? as f64,
+        Err(_) => epoch_seconds(SystemTime::now()),
+    };
+
+    Ok(parse_expr(paths, expr, now).map_err(|err| format!("invalid expression: {}", err))?)
 }
 
 fn _open(filename: &str) -> MyResult<Box<dyn BufRead>> {
@@ -92,3 +437,254 @@ fn _open(filename: &str) -> MyResult<Box<dyn BufRead>> {
         _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
     }
 }
+
+fn parse_expr(paths: Vec<String>, tokens: &[String], now: f64) -> Result<Config, String> {
+    let mut parser = ExprParser {
+        tokens: tokens.iter().peekable(),
+        now,
+        min_depth: 0,
+        max_depth: None,
+    };
+    let mut expr = match tokens {
+        [] => Expr::True,
+        _ => parser.or()?,
+    };
+    if let Some(token) = parser.tokens.next() {
+        return Err(format!("unexpected \"{}\"", token));
+    }
+    if !expr.has_action() {
+        expr = Expr::And(Box::new(expr), Box::new(Expr::Print));
+    }
+
+    Ok(Config {
+        paths,
+        expr,
+        min_depth: parser.min_depth,
+        max_depth: parser.max_depth,
+    })
+}
+
+/// Split a GNU-style numeric argument such as `+5k` into the wanted
+/// ordering of the value against N, N itself and any suffix.
+fn parse_numeric(arg: &str) -> Option<(Ordering, u64, &str)> {
+    let (ordering, rest) = match arg.as_bytes().first() {
+        Some(b'+') => (Ordering::Greater, &arg[1..]),
+        Some(b'-') => (Ordering::Less, &arg[1..]),
+        _ => (Ordering::Equal, arg),
+    };
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let n = rest[..digits].parse().ok()?;
+    Some((ordering, n, &rest[digits..]))
+}
+
+fn is_or(token: &str) -> bool {
+    matches!(token, "-o" | "-or" | "--or")
+}
+
+fn is_and(token: &str) -> bool {
+    matches!(token, "-a" | "-and" | "--and")
+}
+
+fn is_not(token: &str) -> bool {
+    matches!(token, "!" | "-not" | "--not")
+}
+
+/// A recursive descent parser for the expression grammar in
+/// `EXPRESSION_HELP`, one method per level of precedence. Global options
+/// such as -maxdepth are collected on the way and parse as `Expr::True`.
+struct ExprParser<'a> {
+    tokens: Peekable<slice::Iter<'a, String>>,
+    now: f64,
+    min_depth: usize,
+    max_depth: Option<usize>,
+}
+
+impl<'a> ExprParser<'a> {
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while let Some(op) = self.tokens.next_if(|token| is_or(token)) {
+            self.expect_operand(op)?;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        loop {
+            match self.tokens.peek() {
+                None => return Ok(expr),
+                Some(token) if token.as_str() == ")" || is_or(token) => return Ok(expr),
+                Some(token) if is_and(token) => {
+                    let op = self.tokens.next().unwrap();
+                    self.expect_operand(op)?;
+                }
+                // Adjacent expressions are joined with an implicit -a
+                Some(_) => {}
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        match self.tokens.next_if(|token| is_not(token)) {
+            Some(op) => {
+                self.expect_operand(op)?;
+                Ok(Expr::Not(Box::new(self.not()?)))
+            }
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = match self.tokens.next() {
+            None => return Err("expected an expression".to_string()),
+            Some(token) => token.as_str(),
+        };
+
+        match token {
+            "(" => {
+                self.expect_operand(token)?;
+                let expr = self.or()?;
+                match self.tokens.next() {
+                    Some(token) if token == ")" => Ok(expr),
+                    _ => Err("missing \")\"".to_string()),
+                }
+            }
+            "-n" | "-name" | "--name" => self.regex(token).map(Expr::Name),
+            "-path" | "--path" => self.regex(token).map(Expr::Path),
+            "-t" | "-type" | "--type" => {
+                let types = self.argument(token)?;
+                types
+                    .split(',')
+                    .map(|etype| EntryType::from_str(etype, false))
+                    .collect::<Result<_, _>>()
+                    .map(Expr::Type)
+                    .map_err(|_| format!("Invalid {} \"{}\" (expected d, f or l)", token, types))
+            }
+            "-size" | "--size" => {
+                let size = self.argument(token)?;
+                let unit = |suffix| match suffix {
+                    "c" => Some(1),
+                    "w" => Some(2),
+                    "" | "b" => Some(512),
+                    "k" => Some(1 << 10),
+                    "M" => Some(1 << 20),
+                    "G" => Some(1 << 30),
+                    _ => None,
+                };
+                parse_numeric(size)
+                    .and_then(|(ordering, n, suffix)| Some(Expr::Size(ordering, n, unit(suffix)?)))
+                    .ok_or_else(|| {
+                        format!("Invalid {} \"{}\" (expected [+-]N[cwbkMG])", token, size)
+                    })
+            }
+            "-mtime" | "--mtime" => self.age(token, Timestamp::Modified, AgeUnit::Days),
+            "-atime" | "--atime" => self.age(token, Timestamp::Accessed, AgeUnit::Days),
+            "-ctime" | "--ctime" => self.age(token, Timestamp::Changed, AgeUnit::Days),
+            "-mmin" | "--mmin" => self.age(token, Timestamp::Modified, AgeUnit::Minutes),
+            "-amin" | "--amin" => self.age(token, Timestamp::Accessed, AgeUnit::Minutes),
+            "-cmin" | "--cmin" => self.age(token, Timestamp::Changed, AgeUnit::Minutes),
+            "-newer" | "--newer" => {
+                let file = self.argument(token)?;
+                fs::metadata(file)
+                    .and_then(|metadata| Timestamp::Modified.seconds(&metadata))
+                    .map(Expr::Newer)
+                    .map_err(|err| format!("{}: {}", file, err))
+            }
+            "-print" | "--print" => Ok(Expr::Print),
+            "-exec" | "--exec" => self.exec(token, ExecMode::Each),
+            "-ok" | "--ok" => self.exec(token, ExecMode::Ask),
+            "-maxdepth" | "--maxdepth" => {
+                self.max_depth = Some(self.depth(token)?);
+                Ok(Expr::True)
+            }
+            "-mindepth" | "--mindepth" => {
+                self.min_depth = self.depth(token)?;
+                Ok(Expr::True)
+            }
+            _ if token == ")" || is_or(token) || is_and(token) => {
+                Err(format!("expected an expression before \"{}\"", token))
+            }
+            _ => Err(format!("unknown predicate \"{}\"", token)),
+        }
+    }
+
+    /// Fail unless another expression follows `op`.
+    fn expect_operand(&mut self, op: &str) -> Result<(), String> {
+        match self.tokens.peek() {
+            Some(token) if token.as_str() != ")" && !is_or(token) && !is_and(token) => Ok(()),
+            _ => Err(format!("expected an expression after \"{}\"", op)),
+        }
+    }
+
+    fn argument(&mut self, option: &str) -> Result<&'a str, String> {
+        self.tokens
+            .next()
+            .map(String::as_str)
+            .ok_or_else(|| format!("missing argument to \"{}\"", option))
+    }
+
+    fn age(&mut self, option: &str, timestamp: Timestamp, unit: AgeUnit) -> Result<Expr, String> {
+        let age = self.argument(option)?;
+        match parse_numeric(age) {
+            Some((ordering, n, "")) => Ok(Expr::Age {
+                timestamp,
+                ordering,
+                n,
+                unit,
+                now: self.now,
+            }),
+            _ => Err(format!("Invalid {} \"{}\" (expected [+-]N)", option, age)),
+        }
+    }
+
+    fn exec(&mut self, option: &str, mut mode: ExecMode) -> Result<Expr, String> {
+        let mut command = vec![];
+        loop {
+            let arg = self
+                .tokens
+                .next()
+                .ok_or_else(|| format!("missing \";\" to end \"{}\"", option))?;
+            match arg.as_str() {
+                ";" => break,
+                "+" if mode == ExecMode::Each
+                    && command.last().is_some_and(|last| last == "{}") =>
+                {
+                    command.pop();
+                    mode = ExecMode::Batch;
+                    break;
+                }
+                _ => command.push(arg.clone()),
+            }
+        }
+
+        if command.is_empty() {
+            return Err(format!("missing command for \"{}\"", option));
+        }
+        if mode == ExecMode::Batch && command.iter().any(|arg| arg.contains("{}")) {
+            return Err(format!(
+                "only one {{}} is supported with \"{} ... +\"",
+                option
+            ));
+        }
+        Ok(Expr::Exec(Exec::new(mode, command)))
+    }
+
+    fn depth(&mut self, option: &str) -> Result<usize, String> {
+        let depth = self.argument(option)?;
+        depth.parse().map_err(|_| {
+            format!(
+                "Invalid {} \"{}\" (expected a non-negative integer)",
+                option, depth
+            )
+        })
+    }
+
+    fn regex(&mut self, option: &str) -> Result<Regex, String> {
+        let pattern = self.argument(option)?;
+        Regex::new(pattern).map_err(|_| format!("Invalid {} \"{}\"", option, pattern))
+    }
+}
@@ -1,7 +1,13 @@
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
-use std::{borrow::Cow, fs, path::Path};
+use std::{
+    borrow::Cow,
+    env,
+    fs::{self, File, FileTimes},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
 
 type TestResult = Result<(), Box<dyn std::error::Error>>;
 
@@ -39,35 +45,72 @@ fn skips_bad_dir() -> TestResult {
 #[test]
 fn dies_bad_name() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&["--name", "*.csv"])
+        .args(["--name", "*.csv"])
         .assert()
-        .failure();
-    // .stderr(predicate::str::contains("Invalid --name \"*.csv\""));
+        .failure()
+        .stderr(predicate::str::contains("Invalid --name \"*.csv\""));
     Ok(())
 }
 
 // --------------------------------------------------
 #[test]
 fn dies_bad_type() -> TestResult {
-    // let expected = "error: 'x' isn't a valid value for '--type <TYPE>...'";
     Command::cargo_bin(PRG)?
-        .args(&["--type", "x"])
+        .args(["--type", "x"])
         .assert()
-        .failure();
-    // .stderr(predicate::str::contains(expected));
+        .failure()
+        .stderr(predicate::str::contains("Invalid --type \"x\""));
     Ok(())
 }
 
+// --------------------------------------------------
+fn dies(args: &[&str], expected: &str) -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(expected));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_missing_operand() -> TestResult {
+    dies(&["-o", "-n", "a"], r#"expected an expression before "-o""#)?;
+    dies(&["-n", "a", "-o"], r#"expected an expression after "-o""#)?;
+    dies(
+        &["-n", "a", "-a", "-o"],
+        r#"expected an expression after "-a""#,
+    )?;
+    dies(&["!"], r#"expected an expression after "!""#)?;
+    dies(&["(", ")"], r#"expected an expression after "(""#)
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_unbalanced_parens() -> TestResult {
+    dies(&["(", "-n", "a"], r#"missing ")""#)?;
+    dies(&["-n", "a", ")"], r#"unexpected ")""#)
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_predicate() -> TestResult {
+    dies(&["-n", "a", "-foo"], r#"unknown predicate "-foo""#)?;
+    dies(&["-t", "f", "foo"], r#"unknown predicate "foo""#)?;
+    dies(&["-name"], r#"missing argument to "-name""#)
+}
+
 // --------------------------------------------------
 #[cfg(windows)]
-fn format_file_name(expected_file: &str) -> Cow<str> {
+fn format_file_name(expected_file: &str) -> Cow<'_, str> {
     // Equivalent to: Cow::Owned(format!("{}.windows", expected_file))
     format!("{}.windows", expected_file).into()
 }
 
 // --------------------------------------------------
 #[cfg(not(windows))]
-fn format_file_name(expected_file: &str) -> Cow<str> {
+fn format_file_name(expected_file: &str) -> Cow<'_, str> {
     // Equivalent to: Cow::Borrowed(expected_file)
     expected_file.into()
 }
@@ -217,7 +260,7 @@ fn type_l() -> TestResult {
 #[test]
 fn type_f_l() -> TestResult {
     run(
-        &["tests/inputs", "-t", "l", "f"],
+        &["tests/inputs", "-t", "l,f"],
         "tests/expected/type_f_l.txt",
     )
 }
@@ -235,11 +278,92 @@ fn name_csv() -> TestResult {
 #[test]
 fn name_csv_mp3() -> TestResult {
     run(
-        &["tests/inputs", "-n", ".*[.]csv", "-n", ".*[.]mp3"],
+        &["tests/inputs", "-n", ".*[.]csv", "-o", "-n", ".*[.]mp3"],
         "tests/expected/name_csv_mp3.txt",
     )
 }
 
+// --------------------------------------------------
+#[test]
+fn type_f_or_l() -> TestResult {
+    run(
+        &["tests/inputs", "-type", "l", "-or", "-type", "f"],
+        "tests/expected/type_f_l.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn not_type_d() -> TestResult {
+    run(
+        &["tests/inputs", "!", "-t", "d"],
+        "tests/expected/not_type_d.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn not_name_csv_type_f() -> TestResult {
+    run(
+        &["tests/inputs", "-not", "--name", "[.]csv$", "-a", "-t", "f"],
+        "tests/expected/not_name_csv_type_f.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn and_binds_tighter_than_or() -> TestResult {
+    run(
+        &["tests/inputs", "-t", "d", "-o", "-n", "csv", "-t", "f"],
+        "tests/expected/type_d_or_csv_f.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn parens_group_or() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "(",
+            "-t",
+            "d",
+            "-o",
+            "-n",
+            "csv",
+            ")",
+            "-t",
+            "f",
+        ],
+        "tests/expected/type_d_or_csv_and_f.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dirs_b_or_mp3_not_under_d() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "(",
+            "-t",
+            "d",
+            "-n",
+            "^b$",
+            "-o",
+            "-t",
+            "f",
+            "-n",
+            "[.]mp3$",
+            ")",
+            "!",
+            "-path",
+            "inputs.d.",
+        ],
+        "tests/expected/dirs_b_or_mp3_not_under_d.txt",
+    )
+}
+
 // --------------------------------------------------
 #[test]
 fn name_txt_path_a_d() -> TestResult {
@@ -279,6 +403,413 @@ fn path_g() -> TestResult {
     run(&["tests/inputs/g.csv"], "tests/expected/path_g.txt")
 }
 
+// --------------------------------------------------
+#[test]
+fn maxdepth() -> TestResult {
+    run(
+        &["tests/inputs", "-maxdepth", "0"],
+        "tests/expected/path1_maxdepth_0.txt",
+    )?;
+    run(
+        &["tests/inputs", "--maxdepth", "1"],
+        "tests/expected/path1_maxdepth_1.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn mindepth_type_f() -> TestResult {
+    run(
+        &["tests/inputs", "-t", "f", "-mindepth", "3"],
+        "tests/expected/type_f_mindepth_3.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn mindepth_maxdepth_name_b() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "--mindepth",
+            "2",
+            "--maxdepth",
+            "2",
+            "-n",
+            "b",
+        ],
+        "tests/expected/name_b_depth_2.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_depth() -> TestResult {
+    dies(&["-maxdepth", "-1"], r#"Invalid -maxdepth "-1""#)?;
+    dies(&["--mindepth", "x"], r#"Invalid --mindepth "x""#)?;
+    dies(&["-maxdepth"], r#"missing argument to "-maxdepth""#)
+}
+
+// --------------------------------------------------
+#[test]
+fn size_bytes() -> TestResult {
+    run(
+        &["tests/inputs", "-t", "f", "-size", "2c"],
+        "tests/expected/type_f.txt",
+    )?;
+    run(
+        &["tests/inputs", "-size", "12c"],
+        "tests/expected/type_l.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn size_rounds_up() -> TestResult {
+    run(
+        &["tests/inputs", "-t", "f,l", "--size", "1"],
+        "tests/expected/type_f_l.txt",
+    )?;
+    run(
+        &["tests/inputs", "-t", "f,l", "-size", "1k"],
+        "tests/expected/type_f_l.txt",
+    )?;
+    run(
+        &["tests/inputs", "-t", "f", "-size", "-1M"],
+        "tests/expected/empty.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn size_greater_less() -> TestResult {
+    run(
+        &["tests/inputs", "-t", "f,l", "-size", "+2c"],
+        "tests/expected/type_l.txt",
+    )?;
+    run(
+        &["tests/inputs", "-t", "f,l", "-size", "-3c"],
+        "tests/expected/type_f.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn size_name_csv() -> TestResult {
+    run(
+        &["tests/inputs", "-n", "csv", "-size", "-3w", "!", "-t", "d"],
+        "tests/expected/size_name_csv.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_size() -> TestResult {
+    dies(&["-size", "x"], r#"Invalid -size "x""#)?;
+    dies(&["-size", "1q"], r#"Invalid -size "1q""#)?;
+    dies(&["--size", "+"], r#"Invalid --size "+""#)
+}
+
+// --------------------------------------------------
+const NOW: u64 = 1_700_000_000;
+
+// --------------------------------------------------
+fn gen_time_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = env::temp_dir().join(format!("findr-{}", gen_bad_file()));
+    fs::create_dir(&dir)?;
+
+    let ago = |secs| UNIX_EPOCH + Duration::from_secs(NOW - secs);
+    let files = [
+        ("m30", 30, 30),
+        ("m90", 90, 90),
+        ("m150", 150, 150),
+        ("stamp", 100, 100),
+        ("h12", 12 * 3600, 12 * 3600),
+        ("h36", 36 * 3600, 36 * 3600),
+        ("h60", 60 * 3600, 60 * 3600),
+        ("read", 60 * 3600, 30),
+    ];
+    for (name, modified, accessed) in files {
+        let times = FileTimes::new()
+            .set_modified(ago(modified))
+            .set_accessed(ago(accessed));
+        File::create(dir.join(name))?.set_times(times)?;
+    }
+
+    Ok(dir)
+}
+
+// --------------------------------------------------
+fn run_times(args: &[&str], expected: &[&str]) -> TestResult {
+    let dir = gen_time_dir()?;
+    let dirname = dir.to_string_lossy();
+    let cmd = Command::cargo_bin(PRG)?
+        .env("FINDR_NOW", NOW.to_string())
+        .current_dir(&dir)
+        .args(["-t", "f"])
+        .args(args)
+        .assert()
+        .success();
+    fs::remove_dir_all(&dir)?;
+
+    let out = cmd.get_output();
+    let stdout = String::from_utf8(out.stdout.clone())?;
+    let mut names: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.trim_start_matches("./").trim_start_matches(".\\"))
+        .collect();
+    names.sort();
+    assert_eq!(names, expected, "{:?} in {}", args, dirname);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn mtime_days() -> TestResult {
+    run_times(&["-mtime", "0"], &["h12", "m150", "m30", "m90", "stamp"])?;
+    run_times(&["-mtime", "1"], &["h36"])?;
+    run_times(&["-mtime", "+1"], &["h60", "read"])?;
+    run_times(&["-mtime", "+0"], &["h36", "h60", "read"])?;
+    run_times(
+        &["--mtime", "-2"],
+        &["h12", "h36", "m150", "m30", "m90", "stamp"],
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn mmin_minutes() -> TestResult {
+    run_times(&["-mmin", "1"], &["m30"])?;
+    run_times(&["-mmin", "2"], &["m90", "stamp"])?;
+    run_times(&["-mmin", "-2"], &["m30", "m90", "stamp"])?;
+    run_times(&["-mmin", "+2", "-mmin", "-1000"], &["h12", "m150"])?;
+    run_times(&["-mmin", "0"], &[])
+}
+
+// --------------------------------------------------
+#[test]
+fn atime_amin() -> TestResult {
+    run_times(&["-atime", "+1"], &["h60"])?;
+    run_times(&["-amin", "-1"], &["m30", "read"])
+}
+
+// --------------------------------------------------
+#[test]
+fn newer() -> TestResult {
+    run_times(&["-newer", "stamp"], &["m30", "m90"])?;
+    run_times(
+        &["!", "-newer", "stamp", "-mtime", "0"],
+        &["h12", "m150", "stamp"],
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn ctime_cmin() -> TestResult {
+    // The status change times are set by the filesystem, so they are
+    // measured from the real time
+    let dir = gen_time_dir()?;
+    let cmd = Command::cargo_bin(PRG)?
+        .arg(&dir)
+        .args(["-t", "f", "-cmin", "-60", "-ctime", "0"])
+        .assert()
+        .success();
+    let old = Command::cargo_bin(PRG)?
+        .arg(&dir)
+        .args(["-ctime", "+0", "-o", "-cmin", "+60"])
+        .assert()
+        .success();
+    fs::remove_dir_all(&dir)?;
+
+    assert_eq!(
+        String::from_utf8(cmd.get_output().stdout.clone())?
+            .lines()
+            .count(),
+        8
+    );
+    assert!(old.get_output().stdout.is_empty());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_time() -> TestResult {
+    dies(&["-mtime", "x"], r#"Invalid -mtime "x""#)?;
+    dies(&["-cmin", "5k"], r#"Invalid -cmin "5k""#)?;
+    dies(&["-atime"], r#"missing argument to "-atime""#)?;
+    dies(&["-newer", "tests/inputs/nope"], "tests/inputs/nope: ")?;
+    Command::cargo_bin(PRG)?
+        .env("FINDR_NOW", "yesterday")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(r#"Invalid FINDR_NOW "yesterday""#));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(windows))]
+fn exec_each() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "-n",
+            "csv",
+            "-exec",
+            "echo",
+            "found",
+            "{}",
+            ";",
+        ],
+        "tests/expected/exec_name_csv.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(windows))]
+fn exec_as_predicate() -> TestResult {
+    run(
+        &[
+            "tests/inputs",
+            "-t",
+            "f,l",
+            "-exec",
+            "test",
+            "-L",
+            "{}",
+            ";",
+            "-print",
+        ],
+        "tests/expected/type_l.txt",
+    )?;
+    run(
+        &[
+            "tests/inputs",
+            "-t",
+            "f,l",
+            "!",
+            "-exec",
+            "test",
+            "-L",
+            "{}",
+            ";",
+            "-print",
+        ],
+        "tests/expected/type_f.txt",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(windows))]
+fn exec_batch() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "-t", "f", "-exec", "echo", "{}", "+"])
+        .output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    let batches: Vec<&str> = stdout.lines().collect();
+    assert_eq!(batches.len(), 1);
+    let mut files: Vec<&str> = batches[0].split(' ').collect();
+    files.sort();
+    let expected = fs::read_to_string("tests/expected/type_f.txt")?;
+    assert_eq!(files, expected.lines().collect::<Vec<_>>());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(windows))]
+fn exec_batch_respects_arg_max() -> TestResult {
+    let getconf = std::process::Command::new("getconf")
+        .arg("ARG_MAX")
+        .output()?;
+    let arg_max: usize = String::from_utf8(getconf.stdout)?.trim().parse()?;
+    if arg_max > 16 * 1024 * 1024 {
+        return Ok(());
+    }
+
+    // Enough long names to fill one and a half command lines
+    let dir = env::temp_dir().join(format!("findr-{}", gen_bad_file()));
+    fs::create_dir(&dir)?;
+    let count = arg_max * 3 / 2 / 200;
+    for n in 0..count {
+        File::create(dir.join(format!("{:0>200}", n)))?;
+    }
+
+    let output = Command::cargo_bin(PRG)?
+        .arg(&dir)
+        .args(["-t", "f", "-exec", "sh", "-c", "echo $#", "sh", "{}", "+"])
+        .output()?;
+    fs::remove_dir_all(&dir)?;
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    let batches: Vec<usize> = stdout.lines().map(str::parse).collect::<Result<_, _>>()?;
+    assert!(batches.len() > 1);
+    assert_eq!(batches.iter().sum::<usize>(), count);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(windows))]
+fn exec_failures() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "-exec", "false", "{}", ";"])
+        .assert()
+        .success()
+        .stdout("");
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "-exec", "false", "{}", "+"])
+        .assert()
+        .failure()
+        .stderr("one or more commands failed\n");
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", "-maxdepth", "0", "-exec", &bad, ";"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with(format!("{}: ", bad)));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(not(windows))]
+fn ok_asks_first() -> TestResult {
+    let args = ["tests/inputs", "-n", "^g", "-ok", "echo", "yes", "{}", ";"];
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .write_stdin("y\n")
+        .assert()
+        .success()
+        .stdout("yes tests/inputs/g.csv\n")
+        .stderr("< echo yes tests/inputs/g.csv > ? ");
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .write_stdin("n\n")
+        .assert()
+        .success()
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_exec() -> TestResult {
+    dies(&["-exec", "echo", "{}"], r#"missing ";" to end "-exec""#)?;
+    dies(&["-exec", ";"], r#"missing command for "-exec""#)?;
+    dies(&["-exec", "{}", "+"], r#"missing command for "-exec""#)?;
+    dies(
+        &["-exec", "echo", "{}", "{}", "+"],
+        r#"only one {} is supported with "-exec ... +""#,
+    )?;
+    dies(&["-ok", "echo", "{}", "+"], r#"missing ";" to end "-ok""#)
+}
+
 // --------------------------------------------------
 #[test]
 #[cfg(not(windows))]
@@ -293,7 +824,7 @@ fn unreadable_dir() -> TestResult {
     //permissions.set_mode(0o000);
 
     std::process::Command::new("chmod")
-        .args(&["000", dirname])
+        .args(["000", dirname])
         .status()
         .expect("failed");
 
@@ -0,0 +1,98 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+type BenchResult = Result<(), Box<dyn std::error::Error>>;
+
+const PRG: &str = env!("CARGO_BIN_EXE_cutr");
+const TSV: &str = "tests/inputs/movies1.tsv";
+const RUNS: usize = 3;
+
+// --------------------------------------------------
+/// Repeat every line of `input` `times` times.
+fn scale_tall(input: &str, times: usize) -> String {
+    input.repeat(times)
+}
+
+// --------------------------------------------------
+/// Repeat the fields of every line of `input` `times` times across, then
+/// repeat the resulting lines `lines` times.
+fn scale_wide(input: &str, times: usize, lines: usize) -> String {
+    let wide: String = input
+        .lines()
+        .map(|line| format!("{}\n", vec![line; times].join("\t")))
+        .collect();
+    wide.repeat(lines)
+}
+
+// --------------------------------------------------
+fn write_input(name: &str, contents: &str) -> Result<PathBuf, std::io::Error> {
+    let path = env::temp_dir().join(format!("cutr-bench-{}", name));
+    fs::write(&path, contents)?;
+    Ok(path)
+}
+
+// --------------------------------------------------
+fn bench(path: &Path, args: &[&str]) -> Result<Duration, Box<dyn std::error::Error>> {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let status = Command::new(PRG)
+            .arg(path)
+            .args(args)
+            .stdout(Stdio::null())
+            .status()?;
+        let elapsed = start.elapsed();
+
+        if !status.success() {
+            return Err(From::from(format!("cutr {:?} failed: {}", args, status)));
+        }
+        best = best.min(elapsed);
+    }
+    Ok(best)
+}
+
+// --------------------------------------------------
+fn main() -> BenchResult {
+    let input = fs::read_to_string(TSV)?;
+    let inputs = [
+        (
+            "tall",
+            write_input("tall.tsv", &scale_tall(&input, 200_000))?,
+        ),
+        (
+            "wide",
+            write_input("wide.tsv", &scale_wide(&input, 20_000, 100))?,
+        ),
+    ];
+
+    let cases: &[&[&str]] = &[
+        &["-b", "1-8"],
+        &["-b", "2-,1"],
+        &["-c", "1-8"],
+        &["-c", "2-,1"],
+        &["-f", "1,3"],
+        &["-f", "2-"],
+        &["-f", "2", "--complement"],
+    ];
+
+    for (name, path) in &inputs {
+        let megabytes = fs::metadata(path)?.len() as f64 / 1e6;
+        for args in cases {
+            let elapsed = bench(path, args)?;
+            println!(
+                "{:<5} {:<24} {:>8.1} ms {:>8.1} MB/s",
+                name,
+                args.join(" "),
+                elapsed.as_secs_f64() * 1e3,
+                megabytes / elapsed.as_secs_f64()
+            );
+        }
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
@@ -1,17 +1,335 @@
+//! Select bytes, characters or fields from each line, like `cut`.
+//!
+//! [`Selector`] holds the selection logic for use from other crates; `run`
+//! applies one to files as configured by `get_args`.
+
 use crate::Extract::*;
-use clap::Parser;
-use csv::StringRecord;
-use regex::{Captures, Regex};
+use clap::{Parser, ValueEnum};
+use csv::{ByteRecord, ReaderBuilder, StringRecord};
+use regex::{bytes, Captures, Regex};
 use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::VecDeque,
     error::Error,
-    fs::File,
-    io::{self, BufRead, BufReader},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    iter, mem,
     num::NonZeroUsize,
     ops::Range,
+    rc::Rc,
+    str::FromStr,
+    sync::{
+        mpsc::{self, SyncSender},
+        Mutex,
+    },
+    thread,
 };
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
-type PositionList = Vec<Range<usize>>;
+type PositionList = Vec<Position>;
+type Records<'a> = Box<dyn Iterator<Item = MyResult<Line>> + 'a>;
+type Comments = Rc<RefCell<VecDeque<(u64, Vec<u8>)>>>;
+
+/// A zero-based, half-open range of positions. An open range (e.g. `3-`)
+/// has no end and extends to the end of the line or record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    start: usize,
+    end: Option<usize>,
+}
+
+impl Position {
+    /// Resolve against a line or record of `len` positions.
+    fn resolve(&self, len: usize) -> Range<usize> {
+        let end = self.end.map_or(len, |end| end.min(len));
+        self.start.min(end)..end
+    }
+}
+
+impl From<Range<usize>> for Position {
+    fn from(range: Range<usize>) -> Self {
+        Position {
+            start: range.start,
+            end: Some(range.end),
+        }
+    }
+}
+
+impl PartialEq<Range<usize>> for Position {
+    fn eq(&self, other: &Range<usize>) -> bool {
+        *self == Position::from(other.clone())
+    }
+}
+
+/// One end of a range in a position list, counted from the start of the
+/// line or record, or back from its end (`NF` is `FromEnd(1)`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Index {
+    FromStart(usize),
+    FromEnd(usize),
+}
+
+impl Index {
+    fn resolve(&self, len: usize) -> usize {
+        match *self {
+            Index::FromStart(i) => i,
+            Index::FromEnd(n) => len.saturating_sub(n),
+        }
+    }
+
+    /// The index just past this one.
+    fn next(&self) -> Index {
+        match *self {
+            Index::FromStart(i) => Index::FromStart(i + 1),
+            Index::FromEnd(n) => Index::FromEnd(n - 1),
+        }
+    }
+}
+
+/// An entry of a position list as written, which may count from the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    start: Index,
+    end: Option<Index>,
+}
+
+impl Span {
+    fn resolve(&self, len: usize) -> Position {
+        let end = self.end.map_or(len, |end| end.resolve(len).min(len));
+        Position::from(self.start.resolve(len).min(end)..end)
+    }
+
+    /// The equivalent `Position`, if this span does not count from the end.
+    fn fixed(&self) -> Option<Position> {
+        match (self.start, self.end) {
+            (Index::FromStart(start), None | Some(Index::FromEnd(0))) => {
+                Some(Position { start, end: None })
+            }
+            (Index::FromStart(start), Some(Index::FromStart(end))) => Some(Position {
+                start,
+                end: Some(end),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl From<Position> for Span {
+    fn from(pos: Position) -> Self {
+        Span {
+            start: Index::FromStart(pos.start),
+            end: pos.end.map(Index::FromStart),
+        }
+    }
+}
+
+impl PartialEq<Position> for Span {
+    fn eq(&self, other: &Position) -> bool {
+        self.fixed() == Some(*other)
+    }
+}
+
+impl PartialEq<Range<usize>> for Span {
+    fn eq(&self, other: &Range<usize>) -> bool {
+        *self == Position::from(other.clone())
+    }
+}
+
+/// How a `Selector` was built: from a position list, or by combining other
+/// selections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selection {
+    Spans(Vec<Span>),
+    Complement(Box<Selection>),
+    Union(Vec<Selection>),
+}
+
+impl Selection {
+    fn resolve(&self, len: usize) -> PositionList {
+        match self {
+            Selection::Spans(spans) => spans.iter().map(|span| span.resolve(len)).collect(),
+            Selection::Complement(selection) => complement(&selection.resolve(len)),
+            Selection::Union(selections) => selections
+                .iter()
+                .flat_map(|selection| selection.resolve(len))
+                .collect(),
+        }
+    }
+
+    /// The positions this selects in every line, if no entry counts from
+    /// the end.
+    fn fixed(&self) -> Option<PositionList> {
+        match self {
+            Selection::Spans(spans) => spans.iter().map(Span::fixed).collect(),
+            Selection::Complement(selection) => Some(complement(&selection.fixed()?)),
+            Selection::Union(selections) => selections
+                .iter()
+                .map(Selection::fixed)
+                .collect::<Option<Vec<_>>>()
+                .map(|lists| lists.concat()),
+        }
+    }
+}
+
+/// The bytes, characters or fields to keep from each line or record, and
+/// the order to write them in.
+///
+/// A selector is parsed from a list such as `1,3-5,NF` in the syntax of
+/// `cutr -b`, `-c` and `-f`: one-based positions and ranges, open on either
+/// side, where `NF` is the last position and `NF-1` the one before it.
+/// Selectors can be combined with [`Selector::union`] and
+/// [`Selector::complement`], and apply to raw bytes, strings and CSV
+/// records alike.
+///
+/// ```
+/// use cutr::{Order, Selector};
+///
+/// let selector: Selector = "NF,1-2".parse()?;
+/// assert_eq!(selector.select_chars("cutr"), "cur");
+/// assert_eq!(selector.clone().order(Order::AsListed).select_chars("cutr"), "rcu");
+/// assert_eq!(selector.complement().select_bytes(b"cutr"), b"t");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Selector {
+    selection: Selection,
+    order: Order,
+    fixed: Option<PositionList>,
+}
+
+impl Selector {
+    /// Parse a position list, selecting in input order as GNU cut does.
+    pub fn parse(list: &str) -> MyResult<Selector> {
+        Ok(Selector::new(
+            Selection::Spans(parse_pos(list)?),
+            Order::Input,
+        ))
+    }
+
+    fn new(selection: Selection, order: Order) -> Selector {
+        let fixed = selection.fixed().map(|pos_list| arrange(pos_list, order));
+        Selector {
+            selection,
+            order,
+            fixed,
+        }
+    }
+
+    /// Select exactly the given zero-based positions.
+    fn from_positions(pos_list: &[Position], order: Order) -> Selector {
+        let spans = pos_list.iter().map(|&pos| Span::from(pos)).collect();
+        Selector::new(Selection::Spans(spans), order)
+    }
+
+    /// Select each of `pos_list` as a slice of its own, never merged with
+    /// its neighbours, so that schema columns can be written as fields.
+    fn columns(mut pos_list: PositionList, order: Order) -> Selector {
+        if order == Order::Input {
+            pos_list.sort_by_key(|pos| pos.start);
+            pos_list.dedup();
+        }
+        let spans = pos_list.iter().map(|&pos| Span::from(pos)).collect();
+        Selector {
+            selection: Selection::Spans(spans),
+            order,
+            fixed: Some(pos_list),
+        }
+    }
+
+    /// Select every position this selector does not, in input order.
+    pub fn complement(self) -> Selector {
+        Selector::new(Selection::Complement(Box::new(self.selection)), self.order)
+    }
+
+    /// Select the positions of both selectors, keeping the order of `self`.
+    pub fn union(self, other: Selector) -> Selector {
+        let selections = [self.selection, other.selection]
+            .into_iter()
+            .flat_map(|selection| match selection {
+                Selection::Union(selections) => selections,
+                selection => vec![selection],
+            })
+            .collect();
+        Selector::new(Selection::Union(selections), self.order)
+    }
+
+    /// Write positions in input order, or in the order and multiplicity
+    /// listed.
+    pub fn order(self, order: Order) -> Selector {
+        Selector::new(self.selection, order)
+    }
+
+    /// The selected bytes of `line`, concatenated.
+    pub fn select_bytes(&self, line: &[u8]) -> Vec<u8> {
+        byte_slices(line, &self.resolve(line.len()))
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    /// The selected characters of `line`, concatenated.
+    pub fn select_chars(&self, line: &str) -> String {
+        let mut offsets = vec![];
+        char_offsets(line, self.limit(), &mut offsets);
+        char_slices(line, &self.resolve(offsets.len() - 1), &offsets).collect()
+    }
+
+    /// The selected grapheme clusters of `line`, concatenated.
+    pub fn select_graphemes(&self, line: &str) -> String {
+        let mut offsets = vec![];
+        grapheme_offsets(line, self.limit(), &mut offsets);
+        char_slices(line, &self.resolve(offsets.len() - 1), &offsets).collect()
+    }
+
+    /// The characters of `line` that occupy the selected display columns,
+    /// with tabs expanded to every `tab_stop` columns. Wide characters count
+    /// as two columns and are kept whole if either is selected; the selected
+    /// columns of a tab are written as spaces.
+    pub fn select_columns(&self, line: &str, tab_stop: usize) -> String {
+        let mut cells = vec![];
+        let width = display_cells(line, tab_stop, &mut cells);
+        let mut selected = vec![];
+        write_columns(&mut selected, line, &self.resolve(width), &cells)
+            .expect("writing to a Vec cannot fail");
+        String::from_utf8(selected).expect("whole characters are valid UTF-8")
+    }
+
+    /// The selected fields of `record`.
+    pub fn select_fields(&self, record: &StringRecord) -> StringRecord {
+        field_indices(record.len(), &self.resolve(record.len()))
+            .filter_map(|i| record.get(i))
+            .collect()
+    }
+
+    /// The selected fields of `record`, which need not be UTF-8.
+    pub fn select_byte_fields(&self, record: &ByteRecord) -> ByteRecord {
+        field_slices(record, &self.resolve(record.len())).collect()
+    }
+
+    /// The zero-based positions selected in a line or record of `len`
+    /// positions, arranged in output order.
+    fn resolve(&self, len: usize) -> Cow<'_, [Position]> {
+        match &self.fixed {
+            Some(pos_list) => Cow::Borrowed(pos_list),
+            None => Cow::Owned(arrange(self.selection.resolve(len), self.order)),
+        }
+    }
+
+    /// See `max_end`.
+    fn limit(&self) -> Option<usize> {
+        self.fixed.as_deref().and_then(max_end)
+    }
+}
+
+impl FromStr for Selector {
+    type Err = Box<dyn Error>;
+
+    fn from_str(list: &str) -> MyResult<Selector> {
+        Selector::parse(list)
+    }
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -22,7 +340,8 @@ pub struct Args {
     #[arg(
         short = 'b',
         long = "bytes",
-        help = "Select only these bytes",
+        help = "Select only these bytes (NF is the last, NF-1 the one before)",
+        allow_hyphen_values = true,
         conflicts_with = "characters",
         conflicts_with = "fields"
     )]
@@ -31,7 +350,8 @@ pub struct Args {
     #[arg(
         short = 'c',
         long = "chars",
-        help = "Select only these characters",
+        help = "Select only these characters (NF is the last, NF-1 the one before)",
+        allow_hyphen_values = true,
         conflicts_with = "fields",
         conflicts_with = "bytes"
     )]
@@ -40,12 +360,70 @@ pub struct Args {
     #[arg(
         short = 'f',
         long = "fields",
-        help = "Select only these fields",
+        help = "Select only these fields (NF is the last, NF-1 the one before)",
+        allow_hyphen_values = true,
         conflicts_with = "bytes",
         conflicts_with = "characters"
     )]
     fields: Option<String>,
 
+    #[arg(
+        long = "columns",
+        id = "COLUMNS",
+        help = "Select only these display columns (NF is the last, NF-1 the one before)",
+        allow_hyphen_values = true,
+        conflicts_with_all = ["bytes", "characters", "fields", "NAMES"]
+    )]
+    columns: Option<String>,
+
+    #[arg(
+        long = "tab-stop",
+        id = "TAB_STOP",
+        help = "With --columns, expand tabs to every this many columns",
+        default_value = "8"
+    )]
+    tab_stop: NonZeroUsize,
+
+    #[arg(
+        long = "fields-by-name",
+        id = "NAMES",
+        help = "Select only the fields with these header names",
+        conflicts_with_all = ["bytes", "characters", "fields", "complement"]
+    )]
+    field_names: Option<String>,
+
+    #[arg(
+        long = "schema",
+        id = "SCHEMA",
+        help = "Take --fields-by-name from this fixed-width layout, one NAME START WIDTH per line; \
+                with --output-delimiter, the padding around each column is trimmed"
+    )]
+    schema: Option<String>,
+
+    #[arg(long = "drop-header", help = "Do not print the header lines")]
+    drop_header: bool,
+
+    #[arg(
+        long = "header-lines",
+        id = "LINES",
+        help = "Treat the first LINES records as headers, kept even with --only-delimited \
+                [default: 1 with --fields-by-name, otherwise 0]"
+    )]
+    header_lines: Option<usize>,
+
+    #[arg(
+        long = "skip-comments",
+        id = "PREFIX",
+        help = "Pass lines starting with PREFIX through unchanged"
+    )]
+    comment_prefix: Option<String>,
+
+    #[arg(
+        long = "drop-comments",
+        help = "With --skip-comments, leave comment lines out of the output"
+    )]
+    drop_comments: bool,
+
     #[arg(
         short = 'd',
         long = "delim",
@@ -54,29 +432,377 @@ pub struct Args {
         default_value = "\t"
     )]
     delimiter: String,
+
+    #[arg(
+        long = "delim-regex",
+        id = "PATTERN",
+        help = "Split fields on matches of this regex",
+        conflicts_with = "DELIM"
+    )]
+    delim_regex: Option<bytes::Regex>,
+
+    #[arg(
+        short = 'w',
+        long = "whitespace",
+        help = "Split fields on runs of spaces and tabs, ignoring leading \
+                and trailing ones",
+        conflicts_with_all = ["DELIM", "PATTERN"]
+    )]
+    whitespace: bool,
+
+    #[arg(
+        long = "format",
+        help = "Split fields on every delimiter (plain) or as quoted CSV \
+                [default: csv with --fields-by-name, otherwise plain]"
+    )]
+    format: Option<Format>,
+
+    #[arg(
+        long = "quote",
+        id = "QUOTE",
+        help = "Quote character for --format csv",
+        default_value = "\""
+    )]
+    quote: String,
+
+    #[arg(
+        long = "escape",
+        id = "ESCAPE",
+        help = "Escape character for quotes in --format csv [default: doubled quotes]"
+    )]
+    escape: Option<String>,
+
+    #[arg(
+        long = "output-delimiter",
+        id = "OUTPUT_DELIM",
+        help = "Join selected fields with this string \
+                [default: DELIM, tab with --delim-regex, space with --whitespace]"
+    )]
+    output_delimiter: Option<String>,
+
+    #[arg(
+        short = 's',
+        long = "only-delimited",
+        help = "Do not print lines not containing the delimiter"
+    )]
+    only_delimited: bool,
+
+    #[arg(
+        long = "complement",
+        help = "Complement the set of selected bytes, characters or fields"
+    )]
+    complement: bool,
+
+    #[arg(
+        long = "order",
+        help = "Write selections in input order, once each, or as listed \
+                [default: input, or as-listed with --fields-by-name]"
+    )]
+    order: Option<Order>,
+
+    #[arg(
+        long = "output-format",
+        help = "Write selected fields as delimited text, or as JSON Lines objects keyed by \
+                header name or position",
+        default_value = "text"
+    )]
+    output_format: OutputFormat,
+
+    #[arg(
+        long = "json-arrays",
+        help = "With --output-format jsonl, write each record as an array"
+    )]
+    json_arrays: bool,
+
+    #[arg(
+        short = 'g',
+        long = "graphemes",
+        help = "With --chars, count user-perceived characters (grapheme clusters)"
+    )]
+    graphemes: bool,
+
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        id = "JOBS",
+        help = "Cut up to JOBS files at a time, still writing them in order",
+        default_value = "1"
+    )]
+    jobs: NonZeroUsize,
+
+    #[arg(
+        short = 'n',
+        long = "no-split",
+        help = "With --bytes, do not split multi-byte characters",
+        conflicts_with = "raw"
+    )]
+    no_split: bool,
+
+    #[arg(
+        long = "raw",
+        help = "With --bytes, write the selected bytes exactly as they are"
+    )]
+    raw: bool,
 }
 
 #[derive(Debug)]
 pub struct Config {
     paths: Vec<String>,
-    delimiter: u8,
+    delimiter: Delimiter,
+    format: Format,
+    quote: u8,
+    escape: Option<u8>,
+    output_delimiter: String,
+    only_delimited: bool,
+    drop_header: bool,
+    header_lines: usize,
+    comment_prefix: Option<Vec<u8>>,
+    drop_comments: bool,
+    output_format: OutputFormat,
+    json_arrays: bool,
+    delimit_columns: bool,
+    graphemes: bool,
+    tab_stop: usize,
+    jobs: usize,
+    byte_mode: ByteMode,
+    order: Order,
     extract: Extract,
 }
 
+/// Whether selected positions are written in input order with duplicates
+/// removed, as GNU cut does, or in the order and multiplicity listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Order {
+    Input,
+    AsListed,
+}
+
+/// What `--bytes` does with a multi-byte character that is only partly
+/// selected: replace the fragment with U+FFFD, keep the character only if
+/// its trailing bytes are selected, or write the fragment untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteMode {
+    Lossy,
+    NoSplit,
+    Raw,
+}
+
+/// How lines are split into fields. A longer delimiter is matched as an
+/// escaped regex. `Whitespace` splits like awk, on runs of blanks.
+#[derive(Debug)]
+enum Delimiter {
+    Byte(u8),
+    Regex(bytes::Regex),
+    Whitespace,
+}
+
+/// Whether fields are split at every delimiter, like GNU cut, or read and
+/// written as CSV so that quoted delimiters survive the round trip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Plain,
+    Csv,
+}
+
+/// Whether selected fields are written as delimited text or as one JSON
+/// value per record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Jsonl,
+}
+
 #[derive(Debug)]
 pub enum Extract {
-    Fields(PositionList),
-    Bytes(PositionList),
-    Chars(PositionList),
+    Fields(Selector),
+    FieldNames(Vec<String>),
+    Bytes(Selector),
+    Chars(Selector),
+    Columns(Selector),
 }
 
 pub fn run(cfg: Config) -> MyResult<()> {
     // dbg!(cfg);
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    if cfg.jobs > 1 && cfg.paths.len() > 1 {
+        run_parallel(&cfg, &mut out)?;
+        return Ok(out.flush()?);
+    }
 
     for path in &cfg.paths {
         match open(path) {
-            Ok(file) => println!("Opened {}", path),
             Err(err) => eprintln!("{}: {}", path, err),
+            Ok(file) => cut_file(&cfg, path, file, &mut out)?,
+        }
+    }
+
+    Ok(out.flush()?)
+}
+
+/// Cut the files on `--jobs` threads, writing them out in argument order.
+/// Each thread streams its file in chunks over a channel of its own, so the
+/// file being written passes straight through, while a file further on
+/// stops once it has `CHUNKS_QUEUED` chunks waiting. No more than `--jobs`
+/// files are in flight at once.
+fn run_parallel(cfg: &Config, out: &mut impl Write) -> MyResult<()> {
+    let (senders, receivers): (Vec<_>, Vec<_>) = cfg
+        .paths
+        .iter()
+        .map(|_| mpsc::sync_channel(CHUNKS_QUEUED))
+        .unzip();
+    let next = Mutex::new(cfg.paths.iter().zip(senders));
+
+    thread::scope(|scope| {
+        for _ in 0..cfg.jobs.min(cfg.paths.len()) {
+            let next = &next;
+            scope.spawn(move || loop {
+                let Some((path, tx)) = next.lock().unwrap().next() else {
+                    break;
+                };
+
+                let mut chunks = ChunkWriter {
+                    tx: &tx,
+                    buf: vec![],
+                };
+                let res = match open(path) {
+                    Err(err) => {
+                        eprintln!("{}: {}", path, err);
+                        Ok(())
+                    }
+                    Ok(file) => {
+                        cut_file(cfg, path, file, &mut chunks).and_then(|()| Ok(chunks.flush()?))
+                    }
+                };
+                // Errors are not `Send`, so pass on their messages
+                if tx
+                    .send(Chunk::Done(res.map_err(|err| err.to_string())))
+                    .is_err()
+                {
+                    break;
+                }
+            });
+        }
+
+        // Dropping the receivers on an error stops the other threads
+        for rx in receivers {
+            for chunk in rx {
+                match chunk {
+                    Chunk::Data(buf) => out.write_all(&buf)?,
+                    Chunk::Done(res) => {
+                        res?;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHUNKS_QUEUED: usize = 16;
+
+/// A piece of one file's output on its way from `run_parallel`'s threads.
+enum Chunk {
+    Data(Vec<u8>),
+    Done(Result<(), String>),
+}
+
+/// Send whatever is written in `CHUNK_SIZE` pieces, waiting while the
+/// channel is full.
+struct ChunkWriter<'a> {
+    tx: &'a SyncSender<Chunk>,
+    buf: Vec<u8>,
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Chunk::Data(mem::take(&mut self.buf));
+        self.tx
+            .send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output stopped"))
+    }
+}
+
+/// Cut one file to `out`.
+fn cut_file(
+    cfg: &Config,
+    path: &str,
+    file: Box<dyn BufRead>,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    match &cfg.extract {
+        Fields(_) | FieldNames(_) => {
+            cut_fields(cfg, path, field_records(cfg, file), out)?;
+        }
+        Bytes(byte_pos) => {
+            let mut selected = vec![];
+            let mut line_num = 0;
+            for_each_line(file, |line| {
+                line_num += 1;
+                let pos = byte_pos.resolve(line.len());
+                match cfg.byte_mode {
+                    ByteMode::Lossy => {
+                        selected.clear();
+                        byte_slices(line, &pos).for_each(|bytes| selected.extend_from_slice(bytes));
+                        let selected = String::from_utf8_lossy(&selected);
+                        if let Cow::Owned(_) = selected {
+                            eprintln!(
+                                "{}: line {}: invalid UTF-8 replaced with U+FFFD",
+                                path, line_num
+                            );
+                        }
+                        out.write_all(selected.as_bytes())?;
+                    }
+                    ByteMode::NoSplit => {
+                        no_split_slices(line, &pos).try_for_each(|bytes| out.write_all(bytes))?
+                    }
+                    ByteMode::Raw => {
+                        byte_slices(line, &pos).try_for_each(|bytes| out.write_all(bytes))?
+                    }
+                }
+                Ok(out.write_all(b"\n")?)
+            })?;
+        }
+        Chars(char_pos) => {
+            let mut offsets = vec![];
+            let limit = char_pos.limit();
+            let offsets_of: fn(&str, Option<usize>, &mut Vec<usize>) = match cfg.graphemes {
+                true => grapheme_offsets,
+                false => char_offsets,
+            };
+            for_each_str_line(file, path, |line| {
+                offsets_of(line, limit, &mut offsets);
+                let pos = char_pos.resolve(offsets.len() - 1);
+                let mut slices = char_slices(line, &pos, &offsets);
+                if cfg.delimit_columns {
+                    // The padding belongs to the layout, not to the value
+                    let fields = slices.map(|chars| chars.trim_matches(' ').as_bytes());
+                    return Ok(write_fields(cfg, out, fields)?);
+                }
+                slices.try_for_each(|chars| out.write_all(chars.as_bytes()))?;
+                Ok(out.write_all(b"\n")?)
+            })?;
+        }
+        Columns(col_pos) => {
+            let mut cells = vec![];
+            for_each_str_line(file, path, |line| {
+                let width = display_cells(line, cfg.tab_stop, &mut cells);
+                write_columns(out, line, &col_pos.resolve(width), &cells)?;
+                Ok(out.write_all(b"\n")?)
+            })?;
         }
     }
 
@@ -86,28 +812,229 @@ pub fn run(cfg: Config) -> MyResult<()> {
 pub fn get_args() -> MyResult<Config> {
     let args = Args::parse();
 
+    let order = args.order.unwrap_or(match args.field_names {
+        Some(_) => Order::AsListed,
+        None => Order::Input,
+    });
+
+    // The complement of a list is a set, so it has no order of its own
+    if args.complement && args.order == Some(Order::AsListed) {
+        return Err(From::from(
+            "--order as-listed cannot be used with --complement",
+        ));
+    }
+
+    let parse = |list: &str| -> MyResult<Selector> {
+        let selector = Selector::parse(list)?;
+        Ok(match args.complement {
+            true => selector.complement(),
+            false => selector.order(order),
+        })
+    };
+
+    if args.schema.is_some() {
+        if args.field_names.is_none() {
+            return Err(From::from(
+                "--schema can only be used with --fields-by-name",
+            ));
+        }
+        if args.drop_header {
+            return Err(From::from("--drop-header cannot be used with --schema"));
+        }
+    }
+
+    let by_name = args.field_names.is_some();
     let extract = if let Some(pos) = args.fields {
-        Fields(parse_pos(&pos)?)
+        Fields(parse(&pos)?)
+    } else if let Some(names) = args.field_names {
+        let names = parse_names(&names)?;
+        match &args.schema {
+            Some(schema) => Chars(Selector::columns(
+                resolve_columns(&read_schema(schema)?, &names)
+                    .map_err(|err| format!("{}: {}", schema, err))?,
+                order,
+            )),
+            None => FieldNames(names),
+        }
     } else if let Some(pos) = args.bytes {
-        Bytes(parse_pos(&pos)?)
+        Bytes(parse(&pos)?)
     } else if let Some(pos) = args.characters {
-        Chars(parse_pos(&pos)?)
+        Chars(parse(&pos)?)
+    } else if let Some(pos) = args.columns {
+        Columns(parse(&pos)?)
     } else {
-        return Err(From::from("Must have --fields, --bytes, or --chars"));
+        return Err(From::from(
+            "Must have --fields, --fields-by-name, --bytes, --chars, or --columns",
+        ));
     };
 
-    let delim_bytes = args.delimiter.as_bytes();
-    if delim_bytes.len() != 1 {
-        return Err(From::from(format!(
-            "--delim \"{}\" must be a single byte",
-            args.delimiter
-        )));
+    let header_lines = match (args.header_lines, &extract) {
+        (Some(0), FieldNames(_)) => {
+            return Err(From::from(
+                "--header-lines must be at least 1 with --fields-by-name",
+            ));
+        }
+        (Some(lines), _) => lines,
+        (None, FieldNames(_)) => 1,
+        (None, _) => 0,
+    };
+
+    if args.drop_header && header_lines == 0 {
+        return Err(From::from(
+            "--drop-header can only be used with --fields-by-name or --header-lines",
+        ));
+    }
+
+    if args.drop_comments && args.comment_prefix.is_none() {
+        return Err(From::from(
+            "--drop-comments can only be used with --skip-comments",
+        ));
+    }
+
+    if args.comment_prefix.as_deref() == Some("") {
+        return Err(From::from("--skip-comments \"\" must not be empty"));
+    }
+
+    let delimit_columns = args.schema.is_some() && args.output_delimiter.is_some();
+    if !matches!(extract, Fields(_) | FieldNames(_)) && args.schema.is_none() {
+        if args.output_delimiter.is_some() {
+            return Err(From::from(
+                "--output-delimiter can only be used with --fields",
+            ));
+        }
+        if args.format.is_some() {
+            return Err(From::from("--format can only be used with --fields"));
+        }
+    }
+
+    if !matches!(extract, Fields(_) | FieldNames(_)) {
+        if args.only_delimited {
+            return Err(From::from(
+                "--only-delimited can only be used with --fields",
+            ));
+        }
+        if args.output_format != OutputFormat::Text {
+            return Err(From::from("--output-format can only be used with --fields"));
+        }
+        if args.header_lines.is_some() {
+            return Err(From::from("--header-lines can only be used with --fields"));
+        }
+        if args.comment_prefix.is_some() {
+            return Err(From::from("--skip-comments can only be used with --fields"));
+        }
+        if args.delim_regex.is_some() {
+            return Err(From::from("--delim-regex can only be used with --fields"));
+        }
+        if args.whitespace {
+            return Err(From::from("--whitespace can only be used with --fields"));
+        }
+    }
+
+    if args.tab_stop.get() != 8 && !matches!(extract, Columns(_)) {
+        return Err(From::from("--tab-stop can only be used with --columns"));
+    }
+
+    if args.json_arrays && args.output_format != OutputFormat::Jsonl {
+        return Err(From::from(
+            "--json-arrays can only be used with --output-format jsonl",
+        ));
     }
 
+    if args.graphemes && !matches!(extract, Chars(_)) {
+        return Err(From::from("--graphemes can only be used with --chars"));
+    }
+
+    if !matches!(extract, Bytes(_)) {
+        if args.no_split {
+            return Err(From::from("--no-split can only be used with --bytes"));
+        }
+        if args.raw {
+            return Err(From::from("--raw can only be used with --bytes"));
+        }
+    }
+
+    let byte_mode = if args.no_split {
+        ByteMode::NoSplit
+    } else if args.raw {
+        ByteMode::Raw
+    } else {
+        ByteMode::Lossy
+    };
+
+    let delimiter = match args.delim_regex {
+        Some(re) if re.is_match(b"") => {
+            return Err(From::from(format!(
+                "--delim-regex \"{}\" must not match an empty string",
+                re
+            )));
+        }
+        Some(re) => Delimiter::Regex(re),
+        None if args.whitespace => Delimiter::Whitespace,
+        None => match args.delimiter.as_bytes() {
+            [] => return Err(From::from("--delim \"\" must not be empty")),
+            [byte] => Delimiter::Byte(*byte),
+            _ => Delimiter::Regex(bytes::Regex::new(&regex::escape(&args.delimiter))?),
+        },
+    };
+
+    let format = match (args.format, &delimiter) {
+        (Some(Format::Csv), Delimiter::Regex(_) | Delimiter::Whitespace) => {
+            return Err(From::from("--format csv requires a single-byte --delim"));
+        }
+        (Some(format), _) => format,
+        // Header names come from CSV exports, while -f is a drop-in for cut
+        (None, Delimiter::Byte(_)) if by_name => Format::Csv,
+        (None, _) => Format::Plain,
+    };
+
+    if format != Format::Csv && (args.escape.is_some() || args.quote != "\"") {
+        return Err(From::from(
+            "--quote and --escape can only be used with --format csv",
+        ));
+    }
+
+    let single_byte = |name: &str, value: &str| -> MyResult<u8> {
+        match value.as_bytes() {
+            [byte] => Ok(*byte),
+            _ => Err(From::from(format!(
+                "{} \"{}\" must be a single byte",
+                name, value
+            ))),
+        }
+    };
+    let quote = single_byte("--quote", &args.quote)?;
+    let escape = args
+        .escape
+        .map(|escape| single_byte("--escape", &escape))
+        .transpose()?;
+
+    let output_delimiter = match (args.output_delimiter, &delimiter) {
+        (Some(output_delimiter), _) => output_delimiter,
+        (None, Delimiter::Whitespace) => " ".to_string(),
+        (None, _) => args.delimiter,
+    };
+
     Ok(Config {
         paths: args.paths,
-        delimiter: *delim_bytes.first().unwrap(),
-        extract: extract,
+        delimiter,
+        format,
+        quote,
+        escape,
+        output_delimiter,
+        only_delimited: args.only_delimited,
+        drop_header: args.drop_header,
+        header_lines,
+        comment_prefix: args.comment_prefix.map(String::into_bytes),
+        drop_comments: args.drop_comments,
+        output_format: args.output_format,
+        json_arrays: args.json_arrays,
+        delimit_columns,
+        graphemes: args.graphemes,
+        tab_stop: args.tab_stop.get(),
+        jobs: args.jobs.get(),
+        byte_mode,
+        order,
+        extract,
     })
 }
 
@@ -118,33 +1045,515 @@ fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
     }
 }
 
+/// A line of input to `cut_fields`, in the order read.
+#[derive(Debug, PartialEq, Eq)]
+enum Line {
+    Comment(Vec<u8>),
+    Header(ByteRecord),
+    Data(ByteRecord),
+}
+
+/// Read `file` as records, telling the first `--header-lines` apart from
+/// the data and putting comment lines back where they were found.
+fn field_records<'a>(cfg: &'a Config, file: Box<dyn BufRead>) -> Records<'a> {
+    let comments = Comments::default();
+    let file: Box<dyn BufRead> = match &cfg.comment_prefix {
+        Some(prefix) => Box::new(BufReader::new(SkipComments::new(
+            file,
+            prefix.clone(),
+            Rc::clone(&comments),
+        ))),
+        None => file,
+    };
+
+    // Each record with the number of lines read before it
+    let records: Box<dyn Iterator<Item = MyResult<(u64, ByteRecord)>>> =
+        match (&cfg.delimiter, cfg.format) {
+            (Delimiter::Byte(delim), Format::Csv) => Box::new(
+                ReaderBuilder::new()
+                    .delimiter(*delim)
+                    .quote(cfg.quote)
+                    .escape(cfg.escape)
+                    .double_quote(cfg.escape.is_none())
+                    .has_headers(false)
+                    .flexible(true)
+                    .from_reader(KeepBlankLines::new(file, *delim, cfg))
+                    .into_byte_records()
+                    .map(|record| {
+                        let record = record?;
+                        let line = record.position().map_or(0, |pos| pos.line() - 1);
+                        Ok((line, record))
+                    }),
+            ),
+            (&Delimiter::Byte(delim), Format::Plain) => {
+                Box::new(file.split(b'\n').zip(0..).map(move |(line, i)| {
+                    let line = line?;
+                    Ok((
+                        i,
+                        ByteRecord::from(line.split(|&byte| byte == delim).collect::<Vec<_>>()),
+                    ))
+                }))
+            }
+            (Delimiter::Regex(re), _) => {
+                let re = re.clone();
+                Box::new(file.split(b'\n').zip(0..).map(move |(line, i)| {
+                    let line = line?;
+                    Ok((i, ByteRecord::from(re.split(&line).collect::<Vec<_>>())))
+                }))
+            }
+            (Delimiter::Whitespace, _) => Box::new(file.split(b'\n').zip(0..).map(|(line, i)| {
+                let line = line?;
+                Ok((i, ByteRecord::from(split_whitespace(&line))))
+            })),
+        };
+
+    let mut records = records;
+    let mut headers_left = cfg.header_lines;
+    let mut next = None;
+    Box::new(iter::from_fn(move || {
+        if next.is_none() {
+            next = match records.next() {
+                Some(Ok(record)) => Some(record),
+                Some(Err(err)) => return Some(Err(err)),
+                None => None,
+            };
+        }
+
+        // Comments found before the next record, or after the last one
+        let mut comments = comments.borrow_mut();
+        if let Some((before, _)) = comments.front() {
+            if next.as_ref().is_none_or(|(line, _)| before <= line) {
+                let (_, comment) = comments.pop_front()?;
+                return Some(Ok(Line::Comment(comment)));
+            }
+        }
+
+        let (_, record) = next.take()?;
+        if headers_left > 0 {
+            headers_left -= 1;
+            return Some(Ok(Line::Header(record)));
+        }
+        Some(Ok(Line::Data(record)))
+    }))
+}
+
+/// A reader over the lines of `file` that do not start with `prefix`. The
+/// rest are set aside in `comments`, each with the number of lines let
+/// through before it, for `field_records` to put back in order.
+struct SkipComments {
+    file: Box<dyn BufRead>,
+    prefix: Vec<u8>,
+    comments: Comments,
+    passed: u64,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl SkipComments {
+    fn new(file: Box<dyn BufRead>, prefix: Vec<u8>, comments: Comments) -> Self {
+        SkipComments {
+            file,
+            prefix,
+            comments,
+            passed: 0,
+            line: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl Read for SkipComments {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            if self.file.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(0);
+            }
+
+            if self.line.starts_with(&self.prefix) {
+                let mut comment = mem::take(&mut self.line);
+                if comment.last() == Some(&b'\n') {
+                    comment.pop();
+                }
+                self.comments.borrow_mut().push_back((self.passed, comment));
+            } else {
+                self.passed += 1;
+            }
+        }
+
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// A reader over `file` that turns each empty line outside a quoted field
+/// into `""`, which the csv crate would otherwise skip, so that it comes out
+/// as one empty field like it does in plain mode.
+struct KeepBlankLines {
+    file: Box<dyn BufRead>,
+    delim: u8,
+    quote: u8,
+    escape: Option<u8>,
+    double_quote: bool,
+    in_quotes: bool,
+    field_start: bool,
+    closed_quote: bool,
+    escaped: bool,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl KeepBlankLines {
+    fn new(file: Box<dyn BufRead>, delim: u8, cfg: &Config) -> Self {
+        KeepBlankLines {
+            file,
+            delim,
+            quote: cfg.quote,
+            escape: cfg.escape,
+            double_quote: cfg.escape.is_none(),
+            in_quotes: false,
+            field_start: true,
+            closed_quote: false,
+            escaped: false,
+            line: vec![],
+            pos: 0,
+        }
+    }
+
+    /// Follow the quoting through `self.line`, as the csv crate will.
+    fn scan(&mut self) {
+        for &byte in &self.line {
+            if self.escaped {
+                self.escaped = false;
+            } else if self.in_quotes {
+                if Some(byte) == self.escape {
+                    self.escaped = true;
+                } else if byte == self.quote {
+                    self.in_quotes = false;
+                    self.closed_quote = self.double_quote;
+                }
+            } else if mem::take(&mut self.closed_quote) && byte == self.quote {
+                self.in_quotes = true;
+            } else if byte == self.delim || byte == b'\n' || byte == b'\r' {
+                self.field_start = true;
+            } else if mem::take(&mut self.field_start) && byte == self.quote {
+                self.in_quotes = true;
+            }
+        }
+    }
+}
+
+impl Read for KeepBlankLines {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            if self.file.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(0);
+            }
+
+            if !self.in_quotes && matches!(self.line.as_slice(), b"\n" | b"\r\n") {
+                self.line.splice(0..0, [self.quote, self.quote]);
+            }
+            self.scan();
+        }
+
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// The fields of `line` separated by runs of spaces and tabs.
+fn split_whitespace(line: &[u8]) -> Vec<&[u8]> {
+    line.split(|byte| *byte == b' ' || *byte == b'\t')
+        .filter(|field| !field.is_empty())
+        .collect()
+}
+
+fn cut_fields(cfg: &Config, path: &str, records: Records, out: &mut impl Write) -> MyResult<()> {
+    let mut field_pos = match &cfg.extract {
+        Fields(field_pos) => Some(Cow::Borrowed(field_pos)),
+        _ => None,
+    };
+    // The first header line, which names the fields
+    let mut header: Option<ByteRecord> = None;
+
+    for line in records {
+        let (record, is_header) = match line? {
+            Line::Comment(comment) => {
+                if !cfg.drop_comments && cfg.output_format == OutputFormat::Text {
+                    out.write_all(&comment)?;
+                    out.write_all(b"\n")?;
+                }
+                continue;
+            }
+            Line::Header(record) => (record, true),
+            Line::Data(record) => (record, false),
+        };
+
+        if is_header && header.is_none() {
+            if let FieldNames(names) = &cfg.extract {
+                let pos_list =
+                    resolve_names(&record, names).map_err(|err| format!("{}: {}", path, err))?;
+                field_pos = Some(Cow::Owned(Selector::from_positions(&pos_list, cfg.order)));
+            }
+            header = Some(record.clone());
+        }
+
+        if is_header && (cfg.drop_header || cfg.output_format != OutputFormat::Text) {
+            continue;
+        }
+
+        // Lines without the delimiter are not records, so like comments they
+        // have no place among JSON records
+        if record.len() < 2 {
+            if cfg.output_format == OutputFormat::Text && (is_header || !cfg.only_delimited) {
+                write_record(cfg, out, &record, 0..record.len(), header.as_ref())?;
+            }
+            continue;
+        }
+
+        let Some(field_pos) = &field_pos else {
+            unreachable!("header lines come first")
+        };
+        let pos = field_pos.resolve(record.len());
+        write_record(
+            cfg,
+            out,
+            &record,
+            field_indices(record.len(), &pos),
+            header.as_ref(),
+        )?;
+    }
+
+    // Report unknown names even when there is no header to look them up in
+    if let (FieldNames(names), None) = (&cfg.extract, &header) {
+        resolve_names(&ByteRecord::new(), names).map_err(|err| format!("{}: {}", path, err))?;
+    }
+
+    Ok(())
+}
+
+/// Write the fields of `record` at `indices` in the configured output
+/// format.
+fn write_record(
+    cfg: &Config,
+    out: &mut impl Write,
+    record: &ByteRecord,
+    indices: impl Iterator<Item = usize>,
+    header: Option<&ByteRecord>,
+) -> io::Result<()> {
+    let fields = indices.filter_map(|i| Some((i, record.get(i)?)));
+    match cfg.output_format {
+        OutputFormat::Text => write_fields(cfg, out, fields.map(|(_, field)| field)),
+        OutputFormat::Jsonl => write_json(out, fields, header, cfg.json_arrays),
+    }
+}
+
+/// Write fields on one line as a JSON array, or as an object keyed by the
+/// `header` field in the same position or else by one-based position. An
+/// object keeps only the first of any repeated positions, so keys are unique.
+fn write_json<'a>(
+    out: &mut impl Write,
+    fields: impl Iterator<Item = (usize, &'a [u8])>,
+    header: Option<&ByteRecord>,
+    arrays: bool,
+) -> io::Result<()> {
+    out.write_all(if arrays { b"[" } else { b"{" })?;
+    let mut written: Vec<usize> = vec![];
+    for (i, field) in fields {
+        if !arrays && written.contains(&i) {
+            continue;
+        }
+        if !written.is_empty() {
+            out.write_all(b",")?;
+        }
+        written.push(i);
+        if !arrays {
+            match header.and_then(|header| header.get(i)) {
+                Some(name) => write_json_string(out, name)?,
+                None => write_json_string(out, (i + 1).to_string().as_bytes())?,
+            }
+            out.write_all(b":")?;
+        }
+        write_json_string(out, field)?;
+    }
+    out.write_all(if arrays { b"]\n" } else { b"}\n" })
+}
+
+/// Write `value` as a JSON string, replacing invalid UTF-8 with U+FFFD.
+fn write_json_string(out: &mut impl Write, value: &[u8]) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in String::from_utf8_lossy(value).chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_all(c.encode_utf8(&mut [0; 4]).as_bytes())?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+fn write_fields<'a>(
+    cfg: &Config,
+    out: &mut impl Write,
+    fields: impl Iterator<Item = &'a [u8]>,
+) -> io::Result<()> {
+    let delimiter = cfg.output_delimiter.as_bytes();
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.write_all(delimiter)?;
+        }
+        match cfg.format {
+            Format::Plain => out.write_all(field)?,
+            Format::Csv => write_csv_field(out, field, delimiter, cfg.quote, cfg.escape)?,
+        }
+    }
+    out.write_all(b"\n")
+}
+
+/// Write `field`, quoting it if it contains the delimiter, a quote, the
+/// escape character or a line break.
+fn write_csv_field(
+    out: &mut impl Write,
+    field: &[u8],
+    delimiter: &[u8],
+    quote: u8,
+    escape: Option<u8>,
+) -> io::Result<()> {
+    let is_special = |byte: &u8| *byte == quote || Some(*byte) == escape;
+    let needs_quotes = field
+        .iter()
+        .any(|byte| is_special(byte) || *byte == b'\n' || *byte == b'\r')
+        || (!delimiter.is_empty() && field.windows(delimiter.len()).any(|w| w == delimiter));
+
+    if !needs_quotes {
+        return out.write_all(field);
+    }
+
+    out.write_all(&[quote])?;
+    for chunk in field.split_inclusive(is_special) {
+        match chunk.split_last() {
+            Some((last, rest)) if is_special(last) => {
+                out.write_all(rest)?;
+                out.write_all(&[escape.unwrap_or(quote), *last])?;
+            }
+            _ => out.write_all(chunk)?,
+        }
+    }
+    out.write_all(&[quote])
+}
+
+/// Call `f` with each line of `file`, minus its newline, reusing one buffer
+/// so that no line is allocated separately.
+fn for_each_line(
+    mut file: Box<dyn BufRead>,
+    mut f: impl FnMut(&[u8]) -> MyResult<()>,
+) -> MyResult<()> {
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        if file.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(());
+        }
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+        }
+        f(&buf)?;
+    }
+}
+
+/// Like `for_each_line`, but decode each line as UTF-8, replacing invalid
+/// sequences with U+FFFD and saying so on stderr.
+fn for_each_str_line(
+    file: Box<dyn BufRead>,
+    path: &str,
+    mut f: impl FnMut(&str) -> MyResult<()>,
+) -> MyResult<()> {
+    let mut line_num = 0;
+    for_each_line(file, |line| {
+        line_num += 1;
+        let line = String::from_utf8_lossy(line);
+        if let Cow::Owned(_) = line {
+            eprintln!(
+                "{}: line {}: invalid UTF-8 replaced with U+FFFD",
+                path, line_num
+            );
+        }
+        f(&line)
+    })
+}
+
 fn parse_idx(s: &str) -> Result<usize, String> {
     let value_err = || format!("illegal list value: \"{}\"", s);
 
-    s.starts_with('+')
-        .then(|| Err(value_err()))
-        .unwrap_or_else(|| {
-            s.parse::<NonZeroUsize>()
-                .map(|x| usize::from(x) - 1)
-                .map_err(|_| value_err())
-        })
+    if s.starts_with('+') {
+        return Err(value_err());
+    }
+
+    s.parse::<NonZeroUsize>()
+        .map(|x| usize::from(x) - 1)
+        .map_err(|_| value_err())
+}
+
+/// Parse a position that may count back from the end: `NF` is the last
+/// position and `NF-1` the one before it.
+fn parse_index(s: &str) -> Result<Index, String> {
+    match s.strip_prefix("NF") {
+        Some("") => Ok(Index::FromEnd(1)),
+        Some(before) => before
+            .strip_prefix('-')
+            .and_then(|n| parse_idx(n).ok())
+            .and_then(|n| n.checked_add(2))
+            .map(Index::FromEnd)
+            .ok_or_else(|| format!("illegal list value: \"{}\"", s)),
+        None => parse_idx(s).map(Index::FromStart),
+    }
 }
 
-fn parse_range(s: &str) -> Result<Range<usize>, String> {
-    let range_re = Regex::new(r"^(\d+)-(\d+)$").unwrap();
+fn parse_range(s: &str) -> Result<Span, String> {
+    let range_re = Regex::new(r"^(\d*|NF(?:-\d+)?)-(\d*|NF(?:-\d+)?)$").unwrap();
 
-    let extract_range = |caps: Captures| -> Result<Range<usize>, String> {
-        let start = parse_idx(&caps[1])?;
-        let end = parse_idx(&caps[2])?;
-        if start >= end {
-            return Err(format!(
-                "First number in range ({}) must be lower than second number ({})",
-                start + 1,
-                end + 1
-            ));
+    let parse_bound = |bound: &str| -> Result<Option<Index>, String> {
+        match bound {
+            "" => Ok(None),
+            _ => parse_index(bound).map(Some),
         }
+    };
 
-        Ok(start..end + 1)
+    let extract_range = |caps: Captures| -> Result<Span, String> {
+        let start = parse_bound(&caps[1])?;
+        let end = parse_bound(&caps[2])?;
+
+        match (start, end) {
+            (None, None) => Err(format!("illegal list value: \"{}\"", s)),
+            (Some(Index::FromStart(start)), Some(Index::FromStart(end))) if start >= end => {
+                Err(format!(
+                    "First number in range ({}) must be lower than second number ({})",
+                    start + 1,
+                    end + 1
+                ))
+            }
+            (Some(Index::FromEnd(start)), Some(Index::FromEnd(end))) if start <= end => {
+                Err(format!(
+                    "First number in range ({}) must be lower than second number ({})",
+                    &caps[1], &caps[2]
+                ))
+            }
+            (start, end) => Ok(Span {
+                start: start.unwrap_or(Index::FromStart(0)),
+                end: end.map(|end| end.next()),
+            }),
+        }
     };
 
     match range_re.captures(s) {
@@ -153,48 +1562,381 @@ fn parse_range(s: &str) -> Result<Range<usize>, String> {
     }
 }
 
-fn parse_pos(s: &str) -> MyResult<PositionList> {
-    s.split(',')
-        .into_iter()
-        .map(|r| parse_idx(r).map(|x| x..x + 1).or_else(|_| parse_range(r)))
-        .collect::<Result<PositionList, _>>()
-        .map_err(From::from) // TODO: I don't understand what this does or why it's necessary to compile
+fn parse_pos(s: &str) -> MyResult<Vec<Span>> {
+    s.split(',')
+        .map(|r| {
+            parse_index(r)
+                .map(|i| Span {
+                    start: i,
+                    end: Some(i.next()),
+                })
+                .or_else(|_| parse_range(r))
+        })
+        .collect::<Result<Vec<Span>, _>>()
+        .map_err(From::from) // TODO: I don't understand what this does or why it's necessary to compile
+}
+
+fn parse_names(s: &str) -> MyResult<Vec<String>> {
+    s.split(',')
+        .map(|name| match name {
+            "" => Err(format!("illegal field name list: \"{}\"", s)),
+            _ => Ok(name.to_string()),
+        })
+        .collect::<Result<_, _>>()
+        .map_err(From::from)
+}
+
+/// Look up each name in the header, in the order given.
+fn resolve_names(header: &ByteRecord, names: &[String]) -> Result<PositionList, String> {
+    names
+        .iter()
+        .map(|name| {
+            header
+                .iter()
+                .position(|field| field == name.as_bytes())
+                .map(|i| Position::from(i..i + 1))
+                .ok_or_else(|| {
+                    format!(
+                        "unknown field \"{}\" (available: {})",
+                        name,
+                        header
+                            .iter()
+                            .map(String::from_utf8_lossy)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
+/// A named character range of a fixed-width layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Column {
+    name: String,
+    pos: Position,
+}
+
+fn read_schema(path: &str) -> MyResult<Vec<Column>> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    parse_schema(&text).map_err(|err| From::from(format!("{}: {}", path, err)))
+}
+
+/// Parse a fixed-width layout: one `NAME START WIDTH` column per line, with
+/// a one-based starting character. Blank lines and `#` comments are skipped.
+fn parse_schema(text: &str) -> Result<Vec<Column>, String> {
+    let mut columns: Vec<Column> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line_err = |msg: String| format!("line {}: {}", i + 1, msg);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let [name, start, width] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(line_err(format!(
+                "expected NAME START WIDTH, found \"{}\"",
+                line
+            )));
+        };
+        let start = parse_idx(start).map_err(line_err)?;
+        let width = parse_idx(width).map_err(line_err)? + 1;
+        let Some(end) = start.checked_add(width) else {
+            return Err(line_err(format!("column \"{}\" is out of range", name)));
+        };
+        if columns.iter().any(|column| column.name == name) {
+            return Err(line_err(format!("duplicate column \"{}\"", name)));
+        }
+
+        columns.push(Column {
+            name: name.to_string(),
+            pos: Position::from(start..end),
+        });
+    }
+    Ok(columns)
+}
+
+/// Look up each name in the schema, in the order given.
+fn resolve_columns(columns: &[Column], names: &[String]) -> Result<PositionList, String> {
+    let header = columns.iter().map(|column| &column.name).collect();
+    let field_pos = resolve_names(&header, names)?;
+    Ok(field_pos.iter().map(|pos| columns[pos.start].pos).collect())
+}
+
+/// Arrange a resolved position list for output as `--order` says.
+fn arrange(pos_list: PositionList, order: Order) -> PositionList {
+    match order {
+        Order::Input => normalize(&pos_list),
+        Order::AsListed => merge_adjacent(pos_list),
+    }
+}
+
+/// Every position not covered by `pos_list`, in ascending order. Because the
+/// last gap is open-ended, this holds for lines and records of any length.
+fn complement(pos_list: &[Position]) -> PositionList {
+    let mut sorted = pos_list.to_vec();
+    sorted.sort_by_key(|pos| pos.start);
+
+    let mut gaps = vec![];
+    let mut next = Some(0);
+    for pos in sorted {
+        let Some(start) = next else { break };
+        if pos.start > start {
+            gaps.push(Position::from(start..pos.start));
+        }
+        next = pos.end.map(|end| end.max(start));
+    }
+
+    if let Some(start) = next {
+        gaps.push(Position { start, end: None });
+    }
+
+    gaps
+}
+
+/// The positions selected by `pos_list`, each once and in ascending order, as
+/// disjoint ranges.
+fn normalize(pos_list: &[Position]) -> PositionList {
+    let mut sorted = pos_list.to_vec();
+    sorted.sort_by_key(|pos| pos.start);
+
+    let mut merged: PositionList = vec![];
+    for pos in sorted {
+        match merged.last_mut() {
+            Some(last) if last.end.is_none_or(|end| pos.start <= end) => {
+                last.end = last.end.zip(pos.end).map(|(a, b)| a.max(b));
+            }
+            _ => merged.push(pos),
+        }
+    }
+
+    merged
+}
+
+/// Join ranges where one starts exactly where the previous one ends, which
+/// selects the same positions in the same order with fewer slices.
+fn merge_adjacent(pos_list: PositionList) -> PositionList {
+    let mut merged: PositionList = vec![];
+    for pos in pos_list {
+        match merged.last_mut() {
+            Some(last) if last.end == Some(pos.start) => last.end = pos.end,
+            _ => merged.push(pos),
+        }
+    }
+    merged
+}
+
+/// The end of the last position any range can select, or `None` when a
+/// range is open-ended.
+fn max_end(pos_list: &[Position]) -> Option<usize> {
+    pos_list
+        .iter()
+        .try_fold(0, |max, pos| pos.end.map(|end| max.max(end)))
+}
+
+/// Byte offset of every character in `line`, followed by the length of
+/// `line`, so that character range `a..b` is `offsets[a]..offsets[b]`.
+/// With a `limit`, characters past it are not scanned.
+fn char_offsets(line: &str, limit: Option<usize>, offsets: &mut Vec<usize>) {
+    let starts = line.char_indices().map(|(i, _)| i);
+    fill_offsets(starts, line.len(), limit, offsets);
+}
+
+/// Like `char_offsets`, but for extended grapheme clusters, so that a base
+/// character and the combining marks or modifiers after it count as one.
+fn grapheme_offsets(line: &str, limit: Option<usize>, offsets: &mut Vec<usize>) {
+    let starts = line.grapheme_indices(true).map(|(i, _)| i);
+    fill_offsets(starts, line.len(), limit, offsets);
+}
+
+fn fill_offsets(
+    starts: impl Iterator<Item = usize>,
+    len: usize,
+    limit: Option<usize>,
+    offsets: &mut Vec<usize>,
+) {
+    let ends = starts.chain(iter::once(len));
+
+    offsets.clear();
+    match limit {
+        Some(limit) => offsets.extend(ends.take(limit.saturating_add(1))),
+        None => offsets.extend(ends),
+    }
+}
+
+/// The byte offset of every character in `line` and the display columns it
+/// occupies, returning the width of the whole line. Tabs run to the next
+/// multiple of `tab_stop`, and characters of no width share the columns of
+/// the one before them, so that combining marks stay with their base.
+fn display_cells(line: &str, tab_stop: usize, cells: &mut Vec<(usize, Range<usize>)>) -> usize {
+    let mut width = 0;
+    let mut last = 0..0;
+
+    cells.clear();
+    for (i, c) in line.char_indices() {
+        let cols = match (c, c.width().unwrap_or(0)) {
+            ('\t', _) => width..(width / tab_stop + 1) * tab_stop,
+            (_, 0) => last.clone(),
+            (_, w) => width..width + w,
+        };
+        width = width.max(cols.end);
+        last = cols.clone();
+        cells.push((i, cols));
+    }
+
+    width
+}
+
+/// Write the characters that overlap each of `col_pos`, writing spaces for
+/// the selected columns of a tab.
+fn write_columns(
+    out: &mut impl Write,
+    line: &str,
+    col_pos: &[Position],
+    cells: &[(usize, Range<usize>)],
+) -> io::Result<()> {
+    let width = cells.last().map_or(0, |(_, cols)| cols.end);
+    for pos in col_pos {
+        let range = pos.resolve(width);
+        for (k, (i, cols)) in cells.iter().enumerate() {
+            if cols.end <= range.start || cols.start >= range.end {
+                continue;
+            }
+
+            let end = cells.get(k + 1).map_or(line.len(), |(next, _)| *next);
+            match &line[*i..end] {
+                "\t" => {
+                    let spaces = cols.end.min(range.end) - cols.start.max(range.start);
+                    write!(out, "{:1$}", "", spaces)?;
+                }
+                chars => out.write_all(chars.as_bytes())?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn char_slices<'a>(
+    line: &'a str,
+    char_pos: &'a [Position],
+    offsets: &'a [usize],
+) -> impl Iterator<Item = &'a str> {
+    let num_chars = offsets.len() - 1;
+    char_pos.iter().map(move |pos| {
+        let range = pos.resolve(num_chars);
+        &line[offsets[range.start]..offsets[range.end]]
+    })
 }
 
-fn extract_chars(line: &str, char_pos: &[Range<usize>]) -> String {
-    char_pos
+fn byte_slices<'a>(line: &'a [u8], byte_pos: &'a [Position]) -> impl Iterator<Item = &'a [u8]> {
+    byte_pos
         .iter()
-        .cloned() // TODO: why is cloned() necessary here (and why can't we dereference char_pos at the beginning)
-        .flatten()
-        .filter_map(|i| line.chars().nth(i))
-        .collect()
+        .map(move |pos| &line[pos.resolve(line.len())])
 }
 
-fn extract_bytes(line: &str, byte_pos: &[Range<usize>]) -> String {
-    let bytes: Vec<_> = byte_pos // TODO: why is type declaration for `bytes` necessary here
-        .iter()
-        .cloned()
-        .flatten()
-        .filter_map(|i| line.bytes().nth(i))
-        .collect();
+/// Like `byte_slices`, but move the ends of each range back to the nearest
+/// UTF-8 character boundary, so a character is kept when its last byte is
+/// selected. Bytes that are not valid UTF-8 pass through unchanged.
+fn no_split_slices<'a>(line: &'a [u8], byte_pos: &'a [Position]) -> impl Iterator<Item = &'a [u8]> {
+    // Only step back to a lead byte when the sequence it starts is valid and
+    // runs past `i`; a stray continuation byte is a boundary of its own
+    let floor_boundary = move |i: usize| {
+        let lead = (i.saturating_sub(3)..i)
+            .rev()
+            .find(|&j| line[j] & 0xC0 != 0x80);
+        match lead {
+            Some(j) => {
+                let width = match line[j] {
+                    0xC2..=0xDF => 2,
+                    0xE0..=0xEF => 3,
+                    0xF0..=0xF4 => 4,
+                    _ => 1,
+                };
+                let valid = j + width > i
+                    && j + width <= line.len()
+                    && std::str::from_utf8(&line[j..j + width]).is_ok();
+                if valid {
+                    j
+                } else {
+                    i
+                }
+            }
+            None => i,
+        }
+    };
 
-    String::from_utf8_lossy(&bytes).into_owned()
+    byte_pos.iter().map(move |pos| {
+        let range = pos.resolve(line.len());
+        let end = floor_boundary(range.end);
+        let start = floor_boundary(range.start).min(end);
+        &line[start..end]
+    })
 }
 
-fn extract_fields(record: &StringRecord, field_pos: &[Range<usize>]) -> Vec<String> {
-    field_pos
-        .iter()
-        .cloned()
-        .flatten()
-        .filter_map(|i| record.get(i))
-        .map(|x| x.to_string())
-        .collect()
+fn field_indices(len: usize, field_pos: &[Position]) -> impl Iterator<Item = usize> + '_ {
+    field_pos.iter().flat_map(move |pos| pos.resolve(len))
+}
+
+fn field_slices<'a>(
+    record: &'a ByteRecord,
+    field_pos: &'a [Position],
+) -> impl Iterator<Item = &'a [u8]> {
+    field_indices(record.len(), field_pos).filter_map(|i| record.get(i))
 }
 
 #[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
 mod unit_tests {
-    use super::{extract_bytes, extract_chars, extract_fields, parse_idx, parse_pos, parse_range};
-    use csv::StringRecord;
+    use super::{
+        byte_slices, char_offsets, char_slices, complement, display_cells, field_slices,
+        grapheme_offsets, max_end, merge_adjacent, no_split_slices, normalize, parse_idx,
+        parse_index, parse_names, parse_pos, parse_range, parse_schema, resolve_columns,
+        resolve_names, split_whitespace, write_csv_field, write_json, write_json_string, Column,
+        Comments, Index, Order, Position, Selector, SkipComments, Span,
+    };
+    use csv::{ByteRecord, StringRecord};
+    use std::{io::Read, ops::Range, rc::Rc};
+
+    fn pos(ranges: &[Range<usize>]) -> Vec<Position> {
+        ranges.iter().cloned().map(Position::from).collect()
+    }
+
+    fn resolve(list: &str, len: usize) -> Vec<Position> {
+        let selector = Selector::parse(list).unwrap().order(Order::AsListed);
+        selector.resolve(len).into_owned()
+    }
+
+    fn extract_chars(line: &str, char_pos: &[Position]) -> String {
+        let mut offsets = vec![];
+        char_offsets(line, max_end(char_pos), &mut offsets);
+        char_slices(line, char_pos, &offsets).collect()
+    }
+
+    fn extract_graphemes(line: &str, char_pos: &[Position]) -> String {
+        let mut offsets = vec![];
+        grapheme_offsets(line, max_end(char_pos), &mut offsets);
+        char_slices(line, char_pos, &offsets).collect()
+    }
+
+    fn extract_bytes(line: &[u8], byte_pos: &[Position]) -> String {
+        String::from_utf8_lossy(&extract_bytes_raw(line, byte_pos)).into_owned()
+    }
+
+    fn extract_bytes_raw(line: &[u8], byte_pos: &[Position]) -> Vec<u8> {
+        byte_slices(line, byte_pos).collect::<Vec<_>>().concat()
+    }
+
+    fn extract_bytes_no_split(line: &[u8], byte_pos: &[Position]) -> Vec<u8> {
+        no_split_slices(line, byte_pos).collect::<Vec<_>>().concat()
+    }
+
+    fn extract_fields<'a>(record: &'a ByteRecord, field_pos: &'a [Position]) -> Vec<&'a [u8]> {
+        field_slices(record, field_pos).collect()
+    }
+
+    fn fields<'a>(values: &[&'a str]) -> Vec<&'a [u8]> {
+        values.iter().map(|value| value.as_bytes()).collect()
+    }
 
     #[test]
     fn test_parse_pos() {
@@ -250,9 +1992,6 @@ mod unit_tests {
         let res = parse_pos("1,");
         assert!(res.is_err());
 
-        let res = parse_pos("1-");
-        assert!(res.is_err());
-
         let res = parse_pos("1-1-1");
         assert!(res.is_err());
 
@@ -306,6 +2045,165 @@ mod unit_tests {
         let res = parse_pos("15,19-20");
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), vec![14..15, 18..20]);
+
+        // Open-ended and prefix ranges
+        let res = parse_pos("3-");
+        assert!(res.is_ok());
+        assert_eq!(
+            res.unwrap(),
+            vec![Position {
+                start: 2,
+                end: None
+            }]
+        );
+
+        let res = parse_pos("-10");
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![0..10]);
+
+        let res = parse_pos("-2,4-");
+        assert!(res.is_ok());
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                Position::from(0..2),
+                Position {
+                    start: 3,
+                    end: None
+                }
+            ]
+        );
+
+        let res = parse_pos("0-");
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().to_string(), "illegal list value: \"0\"");
+
+        let res = parse_pos("-0");
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().to_string(), "illegal list value: \"0\"");
+
+        let res = parse_pos("--1");
+        assert!(res.is_err());
+
+        // Positions counted from the end
+        let res = parse_pos("NF,NF-2-NF-1,3-NF,NF-");
+        assert!(res.is_ok());
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                Span {
+                    start: Index::FromEnd(1),
+                    end: Some(Index::FromEnd(0))
+                },
+                Span {
+                    start: Index::FromEnd(3),
+                    end: Some(Index::FromEnd(1))
+                },
+                Span {
+                    start: Index::FromStart(2),
+                    end: Some(Index::FromEnd(0))
+                },
+                Span {
+                    start: Index::FromEnd(1),
+                    end: None
+                },
+            ]
+        );
+
+        let res = parse_pos("NF-0");
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().to_string(), "illegal list value: \"0\"");
+
+        let res = parse_pos("NF-1-NF-3");
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "First number in range (NF-1) must be lower than second number (NF-3)"
+        );
+
+        let res = parse_pos("NF+1");
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().to_string(), "illegal list value: \"NF+1\"");
+    }
+
+    #[test]
+    fn test_parse_index() {
+        assert_eq!(parse_index("1"), Ok(Index::FromStart(0)));
+        assert_eq!(parse_index("NF"), Ok(Index::FromEnd(1)));
+        assert_eq!(parse_index("NF-1"), Ok(Index::FromEnd(2)));
+        assert!(parse_index("NF-").is_err());
+        assert!(parse_index("NF-+1").is_err());
+        assert!(parse_index("nf").is_err());
+
+        let res = parse_index("NF-18446744073709551615");
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err(),
+            "illegal list value: \"NF-18446744073709551615\""
+        );
+    }
+
+    #[test]
+    fn test_resolve_positions() {
+        assert_eq!(resolve("NF", 3), pos(&[2..3]));
+        assert_eq!(resolve("NF-1", 3), pos(&[1..2]));
+        assert_eq!(resolve("NF-2-", 3), pos(&[0..3]));
+        assert_eq!(resolve("2-NF-1", 4), pos(&[1..3]));
+        assert_eq!(resolve("NF,1", 3), pos(&[2..3, 0..1]));
+
+        // Beyond the start of short lines
+        assert_eq!(resolve("NF-3", 3), pos(&[0..0]));
+        assert_eq!(resolve("NF", 0), pos(&[0..0]));
+        assert_eq!(resolve("3-NF-1", 3), pos(&[2..2]));
+
+        let selector = Selector::parse("3,1").unwrap();
+        assert_eq!(selector.resolve(9).into_owned(), pos(&[0..1, 2..3]));
+        assert_eq!(selector.limit(), Some(3));
+        assert_eq!(Selector::parse("1,NF").unwrap().limit(), None);
+    }
+
+    #[test]
+    fn test_selector() {
+        let selector: Selector = "NF,1".parse().unwrap();
+        assert_eq!(selector.select_chars("ábc"), "ác");
+        assert_eq!(selector.select_bytes(b"abc"), b"ac");
+        assert_eq!(
+            selector.clone().order(Order::AsListed).select_chars("ábc"),
+            "cá"
+        );
+        assert_eq!(selector.select_chars(""), "");
+
+        let rec = StringRecord::from(vec!["Captain", "Sham", "12345"]);
+        assert_eq!(
+            selector.select_fields(&rec),
+            StringRecord::from(vec!["Captain", "12345"])
+        );
+
+        let rec = ByteRecord::from(vec![&b"Les Mis\xe9rables"[..], b"2012"]);
+        assert_eq!(
+            selector.select_byte_fields(&rec),
+            ByteRecord::from(vec![&b"Les Mis\xe9rables"[..], b"2012"])
+        );
+
+        let complement = selector.complement();
+        assert_eq!(complement.select_chars("ábcd"), "bc");
+        assert_eq!(complement.select_chars("á"), "");
+        assert_eq!(complement.limit(), None);
+
+        let union = Selector::parse("2")
+            .unwrap()
+            .union(Selector::parse("NF-1-").unwrap());
+        assert_eq!(union.select_chars("abcde"), "bde");
+        assert_eq!(union.select_chars("ab"), "ab");
+        assert_eq!(union.clone().complement().select_chars("abcde"), "ac");
+
+        let fixed = Selector::parse("1")
+            .unwrap()
+            .union(Selector::parse("3-").unwrap());
+        assert_eq!(fixed.select_chars("abcde"), "acde");
+        assert_eq!(fixed.complement().limit(), Some(2));
+
+        assert!("0".parse::<Selector>().is_err());
     }
 
     #[test]
@@ -332,34 +2230,511 @@ mod unit_tests {
         assert_eq!(res.unwrap(), 0);
     }
 
+    #[test]
+    fn test_parse_names() {
+        assert!(parse_names("").is_err());
+        assert!(parse_names("title,").is_err());
+        assert!(parse_names(",title").is_err());
+
+        let res = parse_names("title");
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), &["title"]);
+
+        let res = parse_names("director,title year");
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), &["director", "title year"]);
+    }
+
+    #[test]
+    fn test_resolve_names() {
+        let header = ByteRecord::from(vec!["title", "year", "director"]);
+        let names = |s: &str| parse_names(s).unwrap();
+
+        let res = resolve_names(&header, &names("title"));
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![0..1]);
+
+        let res = resolve_names(&header, &names("director,title"));
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![2..3, 0..1]);
+
+        let res = resolve_names(&header, &names("title,author"));
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err(),
+            "unknown field \"author\" (available: title, year, director)"
+        );
+
+        // Names are case sensitive
+        assert!(resolve_names(&header, &names("Title")).is_err());
+    }
+
+    #[test]
+    fn test_parse_schema() {
+        let res = parse_schema("# name start width\ntitle 1 20\n\n  year\t21 4\n");
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                Column {
+                    name: "title".to_string(),
+                    pos: Position::from(0..20),
+                },
+                Column {
+                    name: "year".to_string(),
+                    pos: Position::from(20..24),
+                },
+            ]
+        );
+
+        assert!(parse_schema("").unwrap().is_empty());
+
+        let res = parse_schema("title 1 20\nyear 21");
+        assert_eq!(
+            res.unwrap_err(),
+            "line 2: expected NAME START WIDTH, found \"year 21\""
+        );
+
+        let res = parse_schema("title 0 20");
+        assert_eq!(res.unwrap_err(), "line 1: illegal list value: \"0\"");
+
+        let res = parse_schema("title 1 +2");
+        assert_eq!(res.unwrap_err(), "line 1: illegal list value: \"+2\"");
+
+        let res = parse_schema("title 1 20\nt 18446744073709551615 2");
+        assert_eq!(res.unwrap_err(), "line 2: column \"t\" is out of range");
+
+        let res = parse_schema("title 1 20\ntitle 21 4");
+        assert_eq!(res.unwrap_err(), "line 2: duplicate column \"title\"");
+    }
+
+    #[test]
+    fn test_resolve_columns() {
+        let columns = parse_schema("title 1 20\nyear 21 4\ndirector 25 12").unwrap();
+
+        let res = resolve_columns(&columns, &["director".to_string(), "title".to_string()]);
+        assert_eq!(res.unwrap(), pos(&[24..36, 0..20]));
+
+        let res = resolve_columns(&columns, &["genre".to_string()]);
+        assert_eq!(
+            res.unwrap_err(),
+            "unknown field \"genre\" (available: title, year, director)"
+        );
+    }
+
+    #[test]
+    fn test_complement() {
+        let open = |start| Position { start, end: None };
+
+        assert_eq!(complement(&pos(&[0..1])), vec![open(1)]);
+        assert_eq!(
+            complement(&pos(&[2..4])),
+            vec![Position::from(0..2), open(4)]
+        );
+        assert_eq!(
+            complement(&pos(&[5..6, 1..2])),
+            vec![Position::from(0..1), Position::from(2..5), open(6)]
+        );
+
+        // Overlapping and repeated ranges
+        assert_eq!(complement(&pos(&[0..3, 1..2, 2..5])), vec![open(5)]);
+        assert_eq!(
+            complement(&pos(&[1..2, 1..2])),
+            vec![Position::from(0..1), open(2)]
+        );
+
+        // Open-ended ranges leave nothing after them
+        assert_eq!(complement(&[open(2)]), vec![Position::from(0..2)]);
+        assert!(complement(&[open(0)]).is_empty());
+        assert_eq!(complement(&[open(3), Position::from(0..1)]), pos(&[1..3]));
+
+        assert_eq!(extract_chars("ábcd", &complement(&pos(&[1..2]))), "ácd");
+        assert_eq!(extract_chars("ábcd", &complement(&pos(&[9..10]))), "ábcd");
+        assert_eq!(
+            extract_bytes("ábcd".as_bytes(), &complement(&pos(&[0..2]))),
+            "bcd"
+        );
+        let rec = ByteRecord::from(vec!["Captain", "Sham", "12345"]);
+        assert_eq!(
+            extract_fields(&rec, &complement(&pos(&[1..2]))),
+            fields(&["Captain", "12345"])
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let open = |start| Position { start, end: None };
+
+        assert_eq!(normalize(&pos(&[2..3, 1..2])), vec![1..3]);
+        assert_eq!(normalize(&pos(&[0..1, 0..1])), vec![0..1]);
+        assert_eq!(normalize(&pos(&[4..6, 0..2, 1..3])), vec![0..3, 4..6]);
+        assert_eq!(normalize(&pos(&[0..5, 1..2])), vec![0..5]);
+        assert_eq!(
+            normalize(&[open(3), Position::from(0..1)]),
+            vec![Position::from(0..1), open(3)]
+        );
+        assert_eq!(normalize(&[open(3), Position::from(2..9)]), vec![open(2)]);
+        assert_eq!(normalize(&[Position::from(4..5), open(1)]), vec![open(1)]);
+        assert!(normalize(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_merge_adjacent() {
+        let open = |start| Position { start, end: None };
+
+        assert_eq!(merge_adjacent(pos(&[0..1, 1..2, 2..5])), vec![0..5]);
+        assert_eq!(merge_adjacent(pos(&[0..1, 2..3])), vec![0..1, 2..3]);
+        assert_eq!(
+            merge_adjacent(vec![Position::from(0..2), open(2)]),
+            vec![open(0)]
+        );
+
+        // Order and repeats are preserved
+        assert_eq!(merge_adjacent(pos(&[2..3, 1..2])), vec![2..3, 1..2]);
+        assert_eq!(merge_adjacent(pos(&[0..1, 0..1])), vec![0..1, 0..1]);
+        assert_eq!(merge_adjacent(pos(&[0..2, 1..3])), vec![0..2, 1..3]);
+        assert_eq!(
+            merge_adjacent(vec![open(2), open(2)]),
+            vec![open(2), open(2)]
+        );
+    }
+
+    #[test]
+    fn test_char_offsets() {
+        let mut offsets = vec![];
+        char_offsets("ábc", None, &mut offsets);
+        assert_eq!(offsets, &[0, 2, 3, 4]);
+
+        char_offsets("ábc", Some(1), &mut offsets);
+        assert_eq!(offsets, &[0, 2]);
+
+        char_offsets("ábc", Some(9), &mut offsets);
+        assert_eq!(offsets, &[0, 2, 3, 4]);
+
+        char_offsets("ábc", Some(usize::MAX), &mut offsets);
+        assert_eq!(offsets, &[0, 2, 3, 4]);
+
+        char_offsets("", None, &mut offsets);
+        assert_eq!(offsets, &[0]);
+
+        assert_eq!(max_end(&pos(&[0..1, 4..6, 2..3])), Some(6));
+        assert_eq!(max_end(&resolve("1,3-", 0)), None);
+
+        let selector = Selector::parse("18446744073709551615").unwrap();
+        assert_eq!(selector.select_chars("ábc"), "");
+        assert_eq!(selector.select_bytes(b"abc"), b"");
+    }
+
     #[test]
     fn test_extract_chars() {
-        assert_eq!(extract_chars("", &[0..1]), "".to_string());
-        assert_eq!(extract_chars("ábc", &[0..1]), "á".to_string());
-        assert_eq!(extract_chars("ábc", &[0..1, 2..3]), "ác".to_string());
-        assert_eq!(extract_chars("ábc", &[0..3]), "ábc".to_string());
-        assert_eq!(extract_chars("ábc", &[2..3, 1..2]), "cb".to_string());
-        assert_eq!(extract_chars("ábc", &[0..1, 1..2, 4..5]), "áb".to_string());
+        assert_eq!(extract_chars("", &pos(&[0..1])), "".to_string());
+        assert_eq!(extract_chars("ábc", &pos(&[0..1])), "á".to_string());
+        assert_eq!(extract_chars("ábc", &pos(&[0..1, 2..3])), "ác".to_string());
+        assert_eq!(extract_chars("ábc", &pos(&[0..3])), "ábc".to_string());
+        assert_eq!(extract_chars("ábc", &pos(&[2..3, 1..2])), "cb".to_string());
+        assert_eq!(
+            extract_chars("ábc", &pos(&[0..1, 1..2, 4..5])),
+            "áb".to_string()
+        );
+        assert_eq!(extract_chars("ábc", &resolve("2-", 3)), "bc");
+        assert_eq!(extract_chars("ábc", &resolve("-2", 3)), "áb");
+        assert_eq!(extract_chars("ábc", &resolve("5-", 3)), "");
+        assert_eq!(extract_chars("ábc", &resolve("NF", 3)), "c");
+        assert_eq!(extract_chars("ábc", &resolve("NF-2,NF", 3)), "ác");
+    }
+
+    #[test]
+    fn test_extract_graphemes() {
+        // "é" as "e" followed by a combining acute accent
+        assert_eq!(extract_chars("e\u{301}cole", &pos(&[0..1])), "e");
+        assert_eq!(extract_graphemes("e\u{301}cole", &pos(&[0..1])), "e\u{301}");
+        assert_eq!(extract_graphemes("e\u{301}cole", &pos(&[1..3])), "co");
+
+        // Thumbs up with a skin tone modifier, and a flag
+        assert_eq!(extract_chars("👍🏽🇫🇷", &pos(&[0..1])), "👍");
+        assert_eq!(extract_graphemes("👍🏽🇫🇷", &pos(&[0..1])), "👍🏽");
+        assert_eq!(extract_graphemes("👍🏽🇫🇷", &resolve("NF", 2)), "🇫🇷");
+        assert_eq!(extract_graphemes("👍🏽🇫🇷", &pos(&[2..3])), "");
+
+        assert_eq!(extract_graphemes("", &pos(&[0..1])), "");
+        assert_eq!(extract_graphemes("ábc", &pos(&[2..3, 0..1])), "cá");
+
+        let selector = Selector::parse("1").unwrap();
+        assert_eq!(selector.select_graphemes("e\u{301}cole"), "e\u{301}");
+    }
+
+    #[test]
+    fn test_display_cells() {
+        let mut cells = vec![];
+
+        assert_eq!(display_cells("", 8, &mut cells), 0);
+        assert!(cells.is_empty());
+
+        assert_eq!(display_cells("a東b", 8, &mut cells), 4);
+        assert_eq!(cells, &[(0, 0..1), (1, 1..3), (4, 3..4)]);
+
+        // A combining accent shares the columns of its base
+        assert_eq!(display_cells("e\u{301}x", 8, &mut cells), 2);
+        assert_eq!(cells, &[(0, 0..1), (1, 0..1), (3, 1..2)]);
+
+        assert_eq!(display_cells("ab\tc\t", 4, &mut cells), 8);
+        assert_eq!(
+            cells,
+            &[(0, 0..1), (1, 1..2), (2, 2..4), (3, 4..5), (4, 5..8)]
+        );
+    }
+
+    #[test]
+    fn test_extract_columns() {
+        let select =
+            |list: &str, line: &str| Selector::parse(list).unwrap().select_columns(line, 4);
+
+        assert_eq!(select("1", "a東b"), "a");
+        assert_eq!(select("2", "a東b"), "東");
+        assert_eq!(select("3-4", "a東b"), "東b");
+        assert_eq!(select("5-", "a東b"), "");
+        assert_eq!(select("NF", "a東b"), "b");
+        assert_eq!(select("1", "e\u{301}x"), "e\u{301}");
+        assert_eq!(select("2-3", "a\tb"), "  ");
+        assert_eq!(select("3-5", "a\tb"), "  b");
+        assert_eq!(select("1", ""), "");
+
+        // Either column of a wide character keeps it
+        let complement = Selector::parse("2").unwrap().complement();
+        assert_eq!(complement.select_columns("a東b", 4), "a東b");
+        let complement = Selector::parse("2-3").unwrap().complement();
+        assert_eq!(complement.select_columns("a東b", 4), "ab");
     }
 
     #[test]
     fn test_extract_bytes() {
-        assert_eq!(extract_bytes("ábc", &[0..1]), "�".to_string());
-        assert_eq!(extract_bytes("ábc", &[0..2]), "á".to_string());
-        assert_eq!(extract_bytes("ábc", &[0..3]), "áb".to_string());
-        assert_eq!(extract_bytes("ábc", &[0..4]), "ábc".to_string());
-        assert_eq!(extract_bytes("ábc", &[3..4, 2..3]), "cb".to_string());
-        assert_eq!(extract_bytes("ábc", &[0..2, 5..6]), "á".to_string());
+        assert_eq!(
+            extract_bytes("ábc".as_bytes(), &pos(&[0..1])),
+            "�".to_string()
+        );
+        assert_eq!(
+            extract_bytes("ábc".as_bytes(), &pos(&[0..2])),
+            "á".to_string()
+        );
+        assert_eq!(
+            extract_bytes("ábc".as_bytes(), &pos(&[0..3])),
+            "áb".to_string()
+        );
+        assert_eq!(
+            extract_bytes("ábc".as_bytes(), &pos(&[0..4])),
+            "ábc".to_string()
+        );
+        assert_eq!(
+            extract_bytes("ábc".as_bytes(), &pos(&[3..4, 2..3])),
+            "cb".to_string()
+        );
+        assert_eq!(
+            extract_bytes("ábc".as_bytes(), &pos(&[0..2, 5..6])),
+            "á".to_string()
+        );
+        assert_eq!(extract_bytes("ábc".as_bytes(), &resolve("3-", 4)), "bc");
+        assert_eq!(extract_bytes("ábc".as_bytes(), &resolve("-2", 4)), "á");
+    }
+
+    #[test]
+    fn test_extract_bytes_no_split() {
+        let no_split = |line: &str, byte_pos: &[Position]| {
+            String::from_utf8(extract_bytes_no_split(line.as_bytes(), byte_pos)).unwrap()
+        };
+
+        // "á" is the two bytes 0..2
+        assert_eq!(no_split("ábc", &pos(&[0..1])), "");
+        assert_eq!(no_split("ábc", &pos(&[1..2])), "á");
+        assert_eq!(no_split("ábc", &pos(&[0..2])), "á");
+        assert_eq!(no_split("ábc", &pos(&[1..3])), "áb");
+        assert_eq!(no_split("ábc", &pos(&[3..4, 0..1])), "c");
+        assert_eq!(no_split("ábc", &pos(&[2..3, 9..10])), "b");
+        assert_eq!(no_split("abç", &pos(&[2..3])), "");
+        assert_eq!(no_split("abç", &resolve("3-", 4)), "ç");
+        assert_eq!(no_split("日本", &pos(&[1..4])), "日");
+        assert_eq!(no_split("日本", &pos(&[3..5])), "");
+
+        // Invalid UTF-8 is passed through
+        assert_eq!(
+            extract_bytes_no_split(b"Mis\xe9rables", &pos(&[3..4])),
+            b"\xe9"
+        );
+        assert_eq!(
+            extract_bytes_no_split(b"\xa1\xa1b", &pos(&[1..3])),
+            b"\xa1b"
+        );
+        assert_eq!(extract_bytes_no_split(b"a\xb0b", &pos(&[1..2])), b"\xb0");
+        // A truncated sequence is not a character to keep whole
+        assert_eq!(
+            extract_bytes_no_split(b"\xe6\x97b", &pos(&[1..3])),
+            b"\x97b"
+        );
+        assert_eq!(
+            extract_bytes_no_split(b"\xe6\x97\xa5\xa1", &pos(&[3..4])),
+            b"\xa1"
+        );
+    }
+
+    #[test]
+    fn test_extract_bytes_raw() {
+        assert_eq!(extract_bytes_raw("ábc".as_bytes(), &pos(&[0..1])), b"\xc3");
+        assert_eq!(extract_bytes_raw("ábc".as_bytes(), &pos(&[1..3])), b"\xa1b");
+        assert_eq!(
+            extract_bytes_raw("ábc".as_bytes(), &pos(&[0..4])),
+            "ábc".as_bytes()
+        );
+        assert_eq!(extract_bytes_raw("ábc".as_bytes(), &pos(&[5..6])), b"");
+    }
+
+    #[test]
+    fn test_split_whitespace() {
+        assert_eq!(split_whitespace(b"a b"), fields(&["a", "b"]));
+        assert_eq!(split_whitespace(b"  a \t  b\t"), fields(&["a", "b"]));
+        assert_eq!(
+            split_whitespace(b"root     1  0.0 /sbin/init"),
+            fields(&["root", "1", "0.0", "/sbin/init"])
+        );
+        assert_eq!(split_whitespace(b"a,b"), fields(&["a,b"]));
+        assert!(split_whitespace(b"").is_empty());
+        assert!(split_whitespace(b" \t ").is_empty());
+    }
+
+    #[test]
+    fn test_skip_comments() {
+        let input = "# a\ntitle,year\n#b\nAlien,1979\n\n# c";
+        let comments = Comments::default();
+        let mut reader = SkipComments::new(
+            Box::new(input.as_bytes()),
+            b"#".to_vec(),
+            Rc::clone(&comments),
+        );
+
+        let mut kept = String::new();
+        reader.read_to_string(&mut kept).unwrap();
+        assert_eq!(kept, "title,year\nAlien,1979\n\n");
+        assert_eq!(
+            comments.borrow().iter().cloned().collect::<Vec<_>>(),
+            vec![
+                (0, b"# a".to_vec()),
+                (1, b"#b".to_vec()),
+                (3, b"# c".to_vec())
+            ]
+        );
+
+        // Reads smaller than a line
+        let mut reader = SkipComments::new(
+            Box::new(input.as_bytes()),
+            b"# ".to_vec(),
+            Comments::default(),
+        );
+        let mut buf = [0; 3];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"tit");
+    }
+
+    #[test]
+    fn test_write_csv_field() {
+        let quoted = |field: &str, delimiter: &str, escape: Option<u8>| {
+            let mut out = vec![];
+            write_csv_field(
+                &mut out,
+                field.as_bytes(),
+                delimiter.as_bytes(),
+                b'"',
+                escape,
+            )
+            .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(quoted("Sham", ",", None), "Sham");
+        assert_eq!(quoted("", ",", None), "");
+        assert_eq!(quoted("20,000 Leagues", ",", None), "\"20,000 Leagues\"");
+        assert_eq!(quoted("20,000 Leagues", "\t", None), "20,000 Leagues");
+        assert_eq!(quoted("a::b", "::", None), "\"a::b\"");
+        assert_eq!(quoted("a:b", "::", None), "a:b");
+        assert_eq!(quoted("a,b", "", None), "a,b");
+        assert_eq!(quoted("two\nlines", ",", None), "\"two\nlines\"");
+        assert_eq!(quoted("say \"hi\"", ",", None), "\"say \"\"hi\"\"\"");
+        assert_eq!(quoted("say \"hi\"", ",", Some(b'\\')), "\"say \\\"hi\\\"\"");
+        assert_eq!(quoted("C:\\", ",", Some(b'\\')), "\"C:\\\\\"");
+    }
+
+    #[test]
+    fn test_write_json_string() {
+        let json = |value: &[u8]| {
+            let mut out = vec![];
+            write_json_string(&mut out, value).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(json(b""), r#""""#);
+        assert_eq!(json("Les Misérables".as_bytes()), r#""Les Misérables""#);
+        assert_eq!(json(br#"a "b" \c"#), r#""a \"b\" \\c""#);
+        assert_eq!(json(b"a\tb\r\n"), r#""a\tb\r\n""#);
+        assert_eq!(json(b"\x00\x1b"), r#""\u0000\u001b""#);
+        assert_eq!(json(b"Mis\xe9rables"), "\"Mis\u{FFFD}rables\"");
+    }
+
+    #[test]
+    fn test_write_json() {
+        let json = |header: Option<&ByteRecord>, arrays: bool| {
+            let fields = [(2, &b"1980"[..]), (0, b"Blues")];
+            let mut out = vec![];
+            write_json(&mut out, fields.into_iter(), header, arrays).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let header = ByteRecord::from(vec!["title", "director", "year"]);
+        assert_eq!(
+            json(Some(&header), false),
+            "{\"year\":\"1980\",\"title\":\"Blues\"}\n"
+        );
+        assert_eq!(json(None, false), "{\"3\":\"1980\",\"1\":\"Blues\"}\n");
+        assert_eq!(json(Some(&header), true), "[\"1980\",\"Blues\"]\n");
+
+        // Fields past the end of the header fall back to positions
+        let short = ByteRecord::from(vec!["title"]);
+        assert_eq!(
+            json(Some(&short), false),
+            "{\"3\":\"1980\",\"title\":\"Blues\"}\n"
+        );
     }
 
     #[test]
     fn test_extract_fields() {
-        let rec = StringRecord::from(vec!["Captain", "Sham", "12345"]);
+        let rec = ByteRecord::from(vec!["Captain", "Sham", "12345"]);
+
+        assert_eq!(extract_fields(&rec, &pos(&[0..1])), fields(&["Captain"]));
+        assert_eq!(extract_fields(&rec, &pos(&[1..2])), fields(&["Sham"]));
+        assert_eq!(
+            extract_fields(&rec, &pos(&[0..1, 2..3])),
+            fields(&["Captain", "12345"])
+        );
+        assert_eq!(
+            extract_fields(&rec, &pos(&[0..1, 3..4])),
+            fields(&["Captain"])
+        );
+        assert_eq!(
+            extract_fields(&rec, &pos(&[1..2, 0..1])),
+            fields(&["Sham", "Captain"])
+        );
+        assert_eq!(
+            extract_fields(&rec, &resolve("2-", 3)),
+            fields(&["Sham", "12345"])
+        );
+        assert_eq!(
+            extract_fields(&rec, &resolve("-2", 3)),
+            fields(&["Captain", "Sham"])
+        );
+        assert_eq!(
+            extract_fields(&rec, &resolve("NF,NF-2", 3)),
+            fields(&["12345", "Captain"])
+        );
 
-        assert_eq!(extract_fields(&rec, &[0..1]), &["Captain"]);
-        assert_eq!(extract_fields(&rec, &[1..2]), &["Sham"]);
-        assert_eq!(extract_fields(&rec, &[0..1, 2..3]), &["Captain", "12345"]);
-        assert_eq!(extract_fields(&rec, &[0..1, 3..4]), &["Captain"]);
-        assert_eq!(extract_fields(&rec, &[1..2, 0..1]), &["Sham", "Captain"]);
+        let latin1 = ByteRecord::from(vec![&b"Les Mis\xe9rables"[..], b"2012"]);
+        assert_eq!(
+            extract_fields(&latin1, &pos(&[0..1])),
+            &[b"Les Mis\xe9rables"]
+        );
     }
 }
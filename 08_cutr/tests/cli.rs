@@ -9,6 +9,16 @@ const PRG: &str = "cutr";
 const CSV: &str = "tests/inputs/movies1.csv";
 const TSV: &str = "tests/inputs/movies1.tsv";
 const BOOKS: &str = "tests/inputs/books.tsv";
+const MIXED: &str = "tests/inputs/mixed.tsv";
+const LATIN1: &str = "tests/inputs/movies1.latin1.tsv";
+const QUOTED: &str = "tests/inputs/quoted.tsv";
+const PS: &str = "tests/inputs/ps.txt";
+const FIXED: &str = "tests/inputs/movies1.fixed.txt";
+const SCHEMA: &str = "tests/inputs/movies1.schema";
+const GRAPHEMES: &str = "tests/inputs/graphemes.txt";
+const WIDE: &str = "tests/inputs/wide.txt";
+const COMMENTED: &str = "tests/inputs/commented.tsv";
+const BLANK_CRLF: &str = "tests/inputs/blank_crlf.tsv";
 
 // --------------------------------------------------
 fn random_string() -> String {
@@ -35,7 +45,7 @@ fn skips_bad_file() -> TestResult {
     let bad = gen_bad_file();
     let expected = format!("{}: .* [(]os error 2[)]", bad);
     Command::cargo_bin(PRG)?
-        .args(&["-f", "1", CSV, &bad, TSV])
+        .args(["-f", "1", CSV, &bad, TSV])
         .assert()
         .success()
         .stderr(predicate::str::is_match(expected)?);
@@ -55,7 +65,10 @@ fn dies(args: &[&str], expected: &str) -> TestResult {
 // --------------------------------------------------
 #[test]
 fn dies_not_enough_args() -> TestResult {
-    dies(&[CSV], "Must have --fields, --bytes, or --chars")
+    dies(
+        &[CSV],
+        "Must have --fields, --fields-by-name, --bytes, --chars, or --columns",
+    )
 }
 
 // --------------------------------------------------
@@ -93,16 +106,35 @@ fn dies_bad_digit_chars() -> TestResult {
 fn dies_empty_delimiter() -> TestResult {
     dies(
         &[CSV, "-f", "1", "-d", ""],
-        "--delim \"\" must be a single byte",
+        "--delim \"\" must not be empty",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_delim_regex() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([CSV, "-f", "1", "--delim-regex", "("])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_empty_match_delim_regex() -> TestResult {
+    dies(
+        &[CSV, "-f", "1", "--delim-regex", ",*"],
+        "--delim-regex \",*\" must not match an empty string",
     )
 }
 
 // --------------------------------------------------
 #[test]
-fn dies_bad_delimiter() -> TestResult {
+fn dies_delim_regex_chars() -> TestResult {
     dies(
-        &[CSV, "-f", "1", "-d", ",,"],
-        "--delim \",,\" must be a single byte",
+        &[CSV, "-c", "1", "--delim-regex", ","],
+        "--delim-regex can only be used with --fields",
     )
 }
 
@@ -110,7 +142,7 @@ fn dies_bad_delimiter() -> TestResult {
 #[test]
 fn dies_chars_bytes_fields() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&[CSV, "-c", "1", "-f", "1", "-b", "1"])
+        .args([CSV, "-c", "1", "-f", "1", "-b", "1"])
         .assert()
         .failure();
     Ok(())
@@ -120,7 +152,7 @@ fn dies_chars_bytes_fields() -> TestResult {
 #[test]
 fn dies_bytes_fields() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&[CSV, "-f", "1", "-b", "1"])
+        .args([CSV, "-f", "1", "-b", "1"])
         .assert()
         .failure();
     Ok(())
@@ -130,7 +162,7 @@ fn dies_bytes_fields() -> TestResult {
 #[test]
 fn dies_chars_fields() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&[CSV, "-c", "1", "-f", "1"])
+        .args([CSV, "-c", "1", "-f", "1"])
         .assert()
         .failure();
     Ok(())
@@ -140,7 +172,7 @@ fn dies_chars_fields() -> TestResult {
 #[test]
 fn dies_chars_bytes() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&[CSV, "-c", "1", "-b", "1"])
+        .args([CSV, "-c", "1", "-b", "1"])
         .assert()
         .failure();
     Ok(())
@@ -335,5 +367,996 @@ fn tsv_c1_8() -> TestResult {
 // --------------------------------------------------
 #[test]
 fn repeated_value() -> TestResult {
-    run(&[BOOKS, "-c", "1,1"], "tests/expected/books.c1,1.out")
+    run(
+        &[BOOKS, "-c", "1,1", "--order", "as-listed"],
+        "tests/expected/books.c1,1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_complement_as_listed() -> TestResult {
+    dies(
+        &[BOOKS, "-c", "2,1", "--complement", "--order", "as-listed"],
+        "--order as-listed cannot be used with --complement",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_f2_open() -> TestResult {
+    run(&[TSV, "-f", "2-"], "tests/expected/movies1.tsv.f2-.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_c_prefix2() -> TestResult {
+    run(&[TSV, "-c", "-2"], "tests/expected/movies1.tsv.c-2.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_f2_complement() -> TestResult {
+    run(
+        &[TSV, "-f", "2", "--complement"],
+        "tests/expected/movies1.tsv.f2.complement.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_c2_3_1_complement() -> TestResult {
+    run(
+        &[TSV, "-c", "2-3,1", "--complement"],
+        "tests/expected/movies1.tsv.c2-3,1.complement.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_f3_1_complement() -> TestResult {
+    run(
+        &[CSV, "-f", "3,1", "-d", ",", "--complement"],
+        "tests/expected/movies1.csv.f3,1.dcomma.complement.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_only_delimited_chars() -> TestResult {
+    dies(
+        &[TSV, "-c", "1", "-s"],
+        "--only-delimited can only be used with --fields",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_output_delimiter_bytes() -> TestResult {
+    dies(
+        &[TSV, "-b", "1", "--output-delimiter", ","],
+        "--output-delimiter can only be used with --fields",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_f1_3_output_delimiter() -> TestResult {
+    run(
+        &[TSV, "-f", "1,3", "--output-delimiter", " | "],
+        "tests/expected/movies1.tsv.f1,3.odpipe.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_f2_open_output_delimiter() -> TestResult {
+    run(
+        &[CSV, "-f", "2-", "-d", ",", "--output-delimiter", "\t"],
+        "tests/expected/movies1.csv.f2-.dcomma.odtab.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn mixed_f1_3() -> TestResult {
+    run(&[MIXED, "-f", "1,3"], "tests/expected/mixed.tsv.f1,3.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn mixed_f1_3_only_delimited() -> TestResult {
+    run(
+        &[MIXED, "-f", "1,3", "-s"],
+        "tests/expected/mixed.tsv.f1,3.s.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_unknown_field_name() -> TestResult {
+    dies(
+        &[CSV, "-d", ",", "--fields-by-name", "title,author"],
+        "unknown field \"author\" (available: title, year, director)",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_drop_header_fields() -> TestResult {
+    dies(
+        &[CSV, "-d", ",", "-f", "1", "--drop-header"],
+        "--drop-header can only be used with --fields-by-name",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_fields_by_name_fields() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([CSV, "-f", "1", "--fields-by-name", "title"])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_fields_by_name() -> TestResult {
+    run(
+        &[CSV, "-d", ",", "--fields-by-name", "director,title"],
+        "tests/expected/movies1.csv.ndirector,title.dcomma.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_fields_by_name_drop_header() -> TestResult {
+    run(
+        &[
+            "tests/inputs/books.csv",
+            "-d",
+            ",",
+            "--fields-by-name",
+            "Title,Year",
+            "--output-delimiter",
+            "\t",
+            "--drop-header",
+        ],
+        "tests/expected/books.csv.nTitle,Year.dcomma.odtab.noheader.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multibyte_delimiter() -> TestResult {
+    run(
+        &[
+            "tests/inputs/movies1.colons.txt",
+            "-f",
+            "2,1",
+            "-d",
+            "::",
+            "--order",
+            "as-listed",
+        ],
+        "tests/expected/movies1.colons.txt.f2,1.dcolons.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn regex_delimiter() -> TestResult {
+    run(
+        &[
+            CSV,
+            "-f",
+            "1-2",
+            "--delim-regex",
+            "[,;]",
+            "--output-delimiter",
+            "¦",
+        ],
+        "tests/expected/movies1.csv.f1-2.dregex.odbrokenbar.out",
+    )
+}
+
+// --------------------------------------------------
+fn run_raw(args: &[&str], expected_file: &str) -> TestResult {
+    let expected = fs::read(expected_file)?;
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_no_split_chars() -> TestResult {
+    dies(
+        &[TSV, "-c", "1", "-n"],
+        "--no-split can only be used with --bytes",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_raw_fields() -> TestResult {
+    dies(
+        &[TSV, "-f", "1", "--raw"],
+        "--raw can only be used with --bytes",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_no_split_raw() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([TSV, "-b", "1", "-n", "--raw"])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_b8_no_split() -> TestResult {
+    run(
+        &[TSV, "-b", "8", "-n"],
+        "tests/expected/movies1.tsv.b8.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_b1_8_no_split() -> TestResult {
+    run(
+        &[TSV, "-b", "1-8", "--no-split"],
+        "tests/expected/movies1.tsv.b1-8.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_b8_raw() -> TestResult {
+    run_raw(
+        &[TSV, "-b", "8", "--raw"],
+        "tests/expected/movies1.tsv.b8.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_b1_8_raw() -> TestResult {
+    run_raw(
+        &[TSV, "-b", "1-8", "--raw"],
+        "tests/expected/movies1.tsv.b1-8.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn latin1_f1_3() -> TestResult {
+    run_raw(
+        &[LATIN1, "-f", "1,3"],
+        "tests/expected/movies1.latin1.tsv.f1,3.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn latin1_b5_9_raw() -> TestResult {
+    run_raw(
+        &[LATIN1, "-b", "5-9", "--raw"],
+        "tests/expected/movies1.latin1.tsv.b5-9.raw.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn latin1_b5_9() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([LATIN1, "-b", "5-9"])
+        .assert()
+        .success()
+        .stdout("e\tyea\nBlues\nMis\u{FFFD}r\n")
+        .stderr(format!(
+            "{}: line 3: invalid UTF-8 replaced with U+FFFD\n",
+            LATIN1
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn latin1_c5_9() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([LATIN1, "-c", "5-9"])
+        .assert()
+        .success()
+        .stdout("e\tyea\nBlues\nMis�r\n")
+        .stderr(format!(
+            "{}: line 3: invalid UTF-8 replaced with U+FFFD\n",
+            LATIN1
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_format_chars() -> TestResult {
+    dies(
+        &[CSV, "-c", "1", "--format", "csv"],
+        "--format can only be used with --fields",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_format_csv_multibyte_delimiter() -> TestResult {
+    dies(
+        &[CSV, "-f", "1", "-d", "::", "--format", "csv"],
+        "--format csv requires a single-byte --delim",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_quote_plain() -> TestResult {
+    dies(
+        &[CSV, "-f", "1", "--format", "plain", "--quote", "'"],
+        "--quote and --escape can only be used with --format csv",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_quote() -> TestResult {
+    dies(
+        &[CSV, "-f", "1", "--format", "csv", "--quote", "''"],
+        "--quote \"''\" must be a single byte",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn blank_crlf_f1_2() -> TestResult {
+    run(
+        &[BLANK_CRLF, "-f", "1-2"],
+        "tests/expected/blank_crlf.tsv.f1-2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn blank_crlf_f2_csv() -> TestResult {
+    run(
+        &[BLANK_CRLF, "-f", "2", "--format", "csv"],
+        "tests/expected/blank_crlf.tsv.f2.csv.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn books_f3_csv() -> TestResult {
+    run(
+        &[
+            "tests/inputs/books.csv",
+            "-f",
+            "3",
+            "-d",
+            ",",
+            "--format",
+            "csv",
+        ],
+        "tests/expected/books.csv.f3.dcomma.csv.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn books_f3_plain() -> TestResult {
+    run(
+        &[
+            "tests/inputs/books.csv",
+            "-f",
+            "3",
+            "-d",
+            ",",
+            "--format",
+            "plain",
+        ],
+        "tests/expected/books.csv.f3.dcomma.plain.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn quoted_f3_1_csv() -> TestResult {
+    run(
+        &[
+            QUOTED,
+            "-f",
+            "3,1",
+            "--format",
+            "csv",
+            "--order",
+            "as-listed",
+        ],
+        "tests/expected/quoted.tsv.f3,1.csv.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn quoted_f1_3_plain() -> TestResult {
+    run(
+        &[QUOTED, "-f", "1,3", "--format", "plain"],
+        "tests/expected/quoted.tsv.f1,3.plain.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn quoted_f3_single_quote() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            QUOTED,
+            "-f",
+            "3",
+            "--format",
+            "csv",
+            "--output-delimiter",
+            ",",
+            "--quote",
+            "'",
+        ])
+        .assert()
+        .success()
+        .stdout("tagline\n'\"They''re on a \"\"mission from God\"\"\"'\n'\"In space,'\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn repeated_value_input_order() -> TestResult {
+    run(&[BOOKS, "-c", "1,1"], "tests/expected/books.c1,1.input.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_f3_1_input_order() -> TestResult {
+    run(
+        &[TSV, "-f", "3,1,3", "--order", "input"],
+        "tests/expected/movies1.tsv.f3,1,3.input.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_c3_1_2_input_order() -> TestResult {
+    run(
+        &[TSV, "-c", "3-,1-2,5"],
+        "tests/expected/movies1.tsv.c3-,1-2,5.input.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_fields_by_name_input_order() -> TestResult {
+    run(
+        &[
+            CSV,
+            "-d",
+            ",",
+            "--fields-by-name",
+            "director,title,director",
+            "--order",
+            "input",
+        ],
+        "tests/expected/movies1.csv.ndirector,title.dcomma.input.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_whitespace_delim() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([PS, "-f", "1", "-w", "-d", ","])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_whitespace_bytes() -> TestResult {
+    dies(
+        &[PS, "-b", "1", "-w"],
+        "--whitespace can only be used with --fields",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn ps_f1_4_whitespace() -> TestResult {
+    run(&[PS, "-f", "1,4", "-w"], "tests/expected/ps.txt.f1,4.w.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn ps_f2_open_whitespace() -> TestResult {
+    run(
+        &[PS, "-f", "2-", "--whitespace", "--output-delimiter", ","],
+        "tests/expected/ps.txt.f2-.w.odcomma.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn ps_f_last_whitespace() -> TestResult {
+    run(&[PS, "-w", "-f", "NF"], "tests/expected/ps.txt.fNF.w.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_f_next_to_last() -> TestResult {
+    run(&[TSV, "-f", "NF-1"], "tests/expected/movies1.tsv.fNF-1.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_c_last_three() -> TestResult {
+    run(
+        &[TSV, "-c", "NF-2-"],
+        "tests/expected/movies1.tsv.cNF-2-.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_b_trim_ends() -> TestResult {
+    run(
+        &[TSV, "-b", "2-NF-1"],
+        "tests/expected/movies1.tsv.b2-NF-1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_f_last_complement() -> TestResult {
+    run(
+        &[TSV, "-f", "NF", "--complement"],
+        "tests/expected/movies1.tsv.fNF.complement.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn mixed_f_last_first() -> TestResult {
+    run(
+        &[MIXED, "-f", "NF,1", "--order", "as-listed"],
+        "tests/expected/mixed.tsv.fNF,1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_reversed_range_from_end() -> TestResult {
+    dies(
+        &[TSV, "-f", "NF-NF-2"],
+        "First number in range (NF) must be lower than second number (NF-2)",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_schema_year_title() -> TestResult {
+    run(
+        &[FIXED, "--schema", SCHEMA, "--fields-by-name", "year,title"],
+        "tests/expected/movies1.fixed.txt.nyear,title.schema.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_schema_delimited() -> TestResult {
+    run(
+        &[
+            FIXED,
+            "--schema",
+            SCHEMA,
+            "--fields-by-name",
+            "director,year,title",
+            "--output-delimiter",
+            ",",
+        ],
+        "tests/expected/movies1.fixed.txt.ndirector,year,title.schema.odcomma.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_schema_space_delimited() -> TestResult {
+    run(
+        &[
+            FIXED,
+            "--schema",
+            SCHEMA,
+            "--fields-by-name",
+            "year,director",
+            "--output-delimiter",
+            " ",
+        ],
+        "tests/expected/movies1.fixed.txt.nyear,director.schema.odspace.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_schema_unknown_column() -> TestResult {
+    dies(
+        &[FIXED, "--schema", SCHEMA, "--fields-by-name", "genre"],
+        "tests/inputs/movies1.schema: unknown field \"genre\" (available: title, year, director)",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_schema_without_names() -> TestResult {
+    dies(
+        &[FIXED, "--schema", SCHEMA, "-c", "1-4"],
+        "--schema can only be used with --fields-by-name",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn graphemes_c1() -> TestResult {
+    run(
+        &[GRAPHEMES, "-c", "1"],
+        "tests/expected/graphemes.txt.c1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn graphemes_c1_graphemes() -> TestResult {
+    run(
+        &[GRAPHEMES, "-c", "1", "--graphemes"],
+        "tests/expected/graphemes.txt.c1.g.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn graphemes_c2_graphemes() -> TestResult {
+    run(
+        &[GRAPHEMES, "-c", "2-", "-g"],
+        "tests/expected/graphemes.txt.c2-.g.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_graphemes_without_chars() -> TestResult {
+    dies(
+        &[GRAPHEMES, "-b", "1", "-g"],
+        "--graphemes can only be used with --chars",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn wide_columns5_12() -> TestResult {
+    run(
+        &[WIDE, "--columns", "5-12"],
+        "tests/expected/wide.txt.columns5-12.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn wide_columns13_open() -> TestResult {
+    run(
+        &[WIDE, "--columns", "13-"],
+        "tests/expected/wide.txt.columns13-.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn wide_columns1_6_tab_stop() -> TestResult {
+    run(
+        &[WIDE, "--columns", "1-6", "--tab-stop", "4"],
+        "tests/expected/wide.txt.columns1-6.tabstop4.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_tab_stop_without_columns() -> TestResult {
+    dies(
+        &[WIDE, "-c", "1", "--tab-stop", "4"],
+        "--tab-stop can only be used with --columns",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_names_jsonl() -> TestResult {
+    run(
+        &[
+            CSV,
+            "-d",
+            ",",
+            "--fields-by-name",
+            "title,director",
+            "--output-format",
+            "jsonl",
+        ],
+        "tests/expected/movies1.csv.ntitle,director.dcomma.jsonl.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn mixed_f1_3_jsonl() -> TestResult {
+    run(
+        &[MIXED, "-f", "1,3", "--output-format", "jsonl"],
+        "tests/expected/mixed.tsv.f1,3.jsonl.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn mixed_f3_1_3_as_listed_jsonl() -> TestResult {
+    run(
+        &[
+            MIXED,
+            "-f",
+            "3,1,3",
+            "--order",
+            "as-listed",
+            "--output-format",
+            "jsonl",
+        ],
+        "tests/expected/mixed.tsv.f3,1,3.as-listed.jsonl.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn quoted_f3_1_jsonl_arrays() -> TestResult {
+    run(
+        &[
+            QUOTED,
+            "-f",
+            "3,1",
+            "--format",
+            "csv",
+            "--output-format",
+            "jsonl",
+            "--json-arrays",
+        ],
+        "tests/expected/quoted.tsv.f3,1.jsonl.arrays.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_jsonl_without_fields() -> TestResult {
+    dies(
+        &[TSV, "-c", "1", "--output-format", "jsonl"],
+        "--output-format can only be used with --fields",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_json_arrays_without_jsonl() -> TestResult {
+    dies(
+        &[TSV, "-f", "1", "--json-arrays"],
+        "--json-arrays can only be used with --output-format jsonl",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn commented_f1_3_header_only_delimited() -> TestResult {
+    let expected = "tests/expected/commented.tsv.f1,3.comments.h1.s.out";
+    let args = [
+        COMMENTED,
+        "-f",
+        "1,3",
+        "--skip-comments",
+        "#",
+        "--header-lines",
+        "1",
+        "-s",
+    ];
+    run(&args, expected)?;
+    run(&[&args[..], &["--format", "plain"]].concat(), expected)
+}
+
+// --------------------------------------------------
+#[test]
+fn commented_f3_drop_comments() -> TestResult {
+    run(
+        &[
+            COMMENTED,
+            "-f",
+            "3",
+            "--skip-comments",
+            "#",
+            "--drop-comments",
+        ],
+        "tests/expected/commented.tsv.f3.dropcomments.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn commented_names() -> TestResult {
+    run(
+        &[
+            COMMENTED,
+            "--fields-by-name",
+            "director,title",
+            "--skip-comments",
+            "#",
+        ],
+        "tests/expected/commented.tsv.ndirector,title.comments.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn commented_f1_2_header_jsonl() -> TestResult {
+    run(
+        &[
+            COMMENTED,
+            "-f",
+            "1,2",
+            "--skip-comments",
+            "#",
+            "--header-lines",
+            "1",
+            "--output-format",
+            "jsonl",
+        ],
+        "tests/expected/commented.tsv.f1,2.comments.h1.jsonl.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn commented_f2_header_jsonl() -> TestResult {
+    run(
+        &[
+            COMMENTED,
+            "-f",
+            "2",
+            "--skip-comments",
+            "#",
+            "--header-lines",
+            "1",
+            "--output-format",
+            "jsonl",
+        ],
+        "tests/expected/commented.tsv.f2.comments.h1.jsonl.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn commented_f1_header_lines_only_delimited() -> TestResult {
+    run(
+        &[COMMENTED, "-f", "1", "-s", "--header-lines", "3"],
+        "tests/expected/commented.tsv.f1.h3.s.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_skip_comments_without_fields() -> TestResult {
+    dies(
+        &[COMMENTED, "-c", "1", "--skip-comments", "#"],
+        "--skip-comments can only be used with --fields",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_drop_comments_without_skip_comments() -> TestResult {
+    dies(
+        &[COMMENTED, "-f", "1", "--drop-comments"],
+        "--drop-comments can only be used with --skip-comments",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_names_without_header_lines() -> TestResult {
+    dies(
+        &[
+            COMMENTED,
+            "--fields-by-name",
+            "title",
+            "--header-lines",
+            "0",
+        ],
+        "--header-lines must be at least 1 with --fields-by-name",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_keep_argument_order() -> TestResult {
+    let files = [TSV, BOOKS, MIXED, "tests/inputs/movies2.tsv", TSV];
+    let expected = "tests/expected/many.f1.out";
+    run(&[&["-f", "1"], &files[..]].concat(), expected)?;
+    run(&[&["-f", "1", "-j", "3"], &files[..]].concat(), expected)?;
+    run(
+        &[&["-f", "1", "--jobs", "8"], &files[..]].concat(),
+        expected,
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_stream_large_files() -> TestResult {
+    // Each file's output spans many of the chunks passed between threads
+    let dir = std::env::temp_dir().join(format!("cutr-{}", gen_bad_file()));
+    fs::create_dir(&dir)?;
+    let mut files = vec![];
+    let mut expected = String::new();
+    for k in 0..5 {
+        let path = dir.join(format!("{}.tsv", k));
+        let contents: String = (0..20_000).map(|n| format!("{}-{}\tx\n", k, n)).collect();
+        fs::write(&path, contents)?;
+        expected.extend((0..20_000).map(|n| format!("{}-{}\n", k, n)));
+        files.push(path);
+    }
+
+    let output = Command::cargo_bin(PRG)?
+        .args(["-f", "1", "-j", "2"])
+        .args(&files)
+        .output()?;
+    fs::remove_dir_all(&dir)?;
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_skip_bad_file() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!("{}: .* [(]os error 2[)]", bad);
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "-j", "2", TSV, &bad, TSV])
+        .assert()
+        .success()
+        .stdout("title\nThe Blues Brothers\nLes Misérables\n".repeat(2))
+        .stderr(predicate::str::is_match(expected)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_stop_at_first_error() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--fields-by-name", "title", "-d", ",", "-j", "2"])
+        .args([CSV, "tests/inputs/books.csv", CSV])
+        .assert()
+        .failure()
+        .stdout("title\nThe Blues Brothers\nLes Misérables\n")
+        .stderr(predicate::str::contains(
+            "tests/inputs/books.csv: unknown field \"title\"",
+        ));
+    Ok(())
 }